@@ -1,4 +1,7 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::io::{self, Read};
+use std::process;
 
 fn char_value(c: char) -> i64 {
     match c {
@@ -12,10 +15,581 @@ fn str_value(s: &str) -> i64 {
     s.chars().map(char_value).fold(0, |acc, v| acc + v)
 }
 
+#[derive(Debug, PartialEq)]
+struct InvalidChar {
+    offset: usize,
+    character: char,
+}
+
+// one newline is allowed at the very end, and isn't a step
+fn strip_newline(bytes: &[u8]) -> &[u8] {
+    bytes.strip_suffix(b"\n").unwrap_or(bytes)
+}
+
+fn first_invalid(bytes: &[u8]) -> Option<InvalidChar> {
+    let offset = bytes.iter().position(|&b| b != b'(' && b != b')')?;
+    // a character is at most 4 bytes, anything else isn't utf-8
+    let rest = &bytes[offset..bytes.len().min(offset + 4)];
+    let character = String::from_utf8_lossy(rest).chars().next().unwrap();
+
+    Some(InvalidChar { offset, character })
+}
+
+// only parentheses are allowed, checked before anything is decoded
+fn validate(bytes: &[u8]) -> Result<(), InvalidChar> {
+    match first_invalid(strip_newline(bytes)) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn test_validate() {
+    let examples = [
+        ("", Ok(())),
+        ("(())", Ok(())),
+        ("(())\n", Ok(())),
+        ("\n", Ok(())),
+        ("(()x)", Err(InvalidChar { offset: 3, character: 'x' })),
+        ("((\n\n", Err(InvalidChar { offset: 2, character: '\n' })),
+        ("((\r\n", Err(InvalidChar { offset: 2, character: '\r' })),
+        (" ()", Err(InvalidChar { offset: 0, character: ' ' })),
+        ("(\u{e9})", Err(InvalidChar { offset: 1, character: '\u{e9}' })),
+    ];
+    for &(s, ref v) in examples.iter() {
+        assert_eq!( &validate(s.as_bytes()), v );
+    }
+
+    let invalid = InvalidChar { offset: 1, character: '\u{fffd}' };
+    assert_eq!( validate(b"(\xff)"), Err(invalid) );
+}
+
+// the floor santa is on after each character
+struct Floors<I: Iterator<Item=char>> {
+    chars: I,
+    floor: i64,
+}
+
+impl<I: Iterator<Item=char>> Floors<I> {
+    fn from_chars<U>(chars: U) -> Self where
+        U: IntoIterator<IntoIter=I,Item=char>
+    {
+        Floors { chars: chars.into_iter(), floor: 0 }
+    }
+}
+
+impl<I: Iterator<Item=char>> Iterator for Floors<I> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        self.chars.next().map(|c| {
+            self.floor += char_value(c);
+            self.floor
+        })
+    }
+}
+
+#[test]
+fn test_floors() {
+    let examples: Vec<(&str, Vec<i64>)> = vec![
+        ("", vec![]),
+        ("(()", vec![1, 2, 1]),
+        ("))x(", vec![-1, -2, -2, -1]),
+    ];
+    for (s, floors) in examples.into_iter() {
+        assert_eq!( Floors::from_chars(s.chars()).collect::<Vec<_>>(), floors );
+    }
+}
+
+// 1-based position of the first character that lands on the target
+fn first_position_reaching(s: &str, target: i64) -> Option<usize> {
+    Floors::from_chars(s.chars())
+        .position(|floor| floor == target)
+        .map(|i| i + 1)
+}
+
+fn basement_position(s: &str) -> Option<usize> {
+    first_position_reaching(s, -1)
+}
+
+#[test]
+fn test_basement_position() {
+    let examples = [
+        (")", Some(1)),
+        ("()())", Some(5)),
+        ("(()))", Some(5)),
+        ("(((", None),
+        ("", None),
+    ];
+    for &(s, p) in examples.iter() {
+        assert_eq!( basement_position(s), p );
+    }
+}
+
+#[test]
+fn test_first_position_reaching() {
+    let examples = [
+        ("(((", 3, Some(3)),
+        ("(((", 1, Some(1)),
+        ("((()", 3, Some(3)),
+        ("()()", 0, Some(2)),
+        ("))((", -2, Some(2)),
+        ("))((", 2, None),
+    ];
+    for &(s, target, p) in examples.iter() {
+        assert_eq!( first_position_reaching(s, target), p );
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Trajectory {
+    lowest: i64,
+    highest: i64,
+    // steps spent on each floor, counting the ground floor at the start
+    time_on: BTreeMap<i64, usize>,
+    // times santa passed through each floor without turning back
+    crossings: BTreeMap<i64, usize>,
+}
+
+fn trajectory<I: Iterator<Item=i64>>(floors: I) -> Trajectory {
+    let mut summary = Trajectory {
+        lowest: 0,
+        highest: 0,
+        time_on: BTreeMap::new(),
+        crossings: BTreeMap::new(),
+    };
+    summary.time_on.insert(0, 1);
+
+    let mut previous = 0;
+    // which way santa was going on arriving at the current floor
+    let mut arrived_going = 0;
+
+    for floor in floors {
+        *summary.time_on.entry(floor).or_insert(0) += 1;
+        summary.lowest = summary.lowest.min(floor);
+        summary.highest = summary.highest.max(floor);
+
+        if floor != previous {
+            let going = floor - previous;
+            if going == arrived_going {
+                *summary.crossings.entry(previous).or_insert(0) += 1;
+            }
+            arrived_going = going;
+        }
+
+        previous = floor;
+    }
+
+    summary
+}
+
+#[test]
+fn test_trajectory() {
+    let summary = trajectory(Floors::from_chars("(()))x)(".chars()));
+
+    assert_eq!(summary.lowest, -2);
+    assert_eq!(summary.highest, 2);
+
+    let time_on: Vec<_> = summary.time_on.into_iter().collect();
+    assert_eq!(time_on, vec![(-2, 1), (-1, 3), (0, 2), (1, 2), (2, 1)]);
+
+    // 1 is passed on the way up and again on the way down, 0 and -1
+    // only on the way down, and 2 and -2 are where santa turns back
+    let crossings: Vec<_> = summary.crossings.into_iter().collect();
+    assert_eq!(crossings, vec![(-1, 1), (0, 1), (1, 2)]);
+}
+
+fn trajectory_report(s: &str) -> String {
+    let summary = trajectory(Floors::from_chars(s.chars()));
+
+    let mut report = format!(
+        "final floor: {}\nlowest floor: {}\nhighest floor: {}\n",
+        str_value(s), summary.lowest, summary.highest);
+
+    report.push_str("floor  steps  crossings\n");
+    for (floor, steps) in summary.time_on {
+        let crossings = summary.crossings.get(&floor).cloned().unwrap_or(0);
+        report.push_str(
+            &format!("{:5}  {:5}  {:9}\n", floor, steps, crossings));
+    }
+
+    report
+}
+
+fn trajectory_csv(s: &str) -> String {
+    let mut csv = String::from("step,floor\n0,0\n");
+
+    for (i, floor) in Floors::from_chars(s.chars()).enumerate() {
+        csv.push_str(&format!("{},{}\n", i + 1, floor));
+    }
+
+    csv
+}
+
+#[test]
+fn test_trajectory_output() {
+    assert_eq!( trajectory_csv("()"), "step,floor\n0,0\n1,1\n2,0\n" );
+    assert_eq!( trajectory_report("(()"), concat!(
+        "final floor: 1\n",
+        "lowest floor: 0\n",
+        "highest floor: 2\n",
+        "floor  steps  crossings\n",
+        "    0      1          0\n",
+        "    1      2          1\n",
+        "    2      1          0\n",
+    ));
+}
+
+// every byte of a word set to the same value
+const ONES: u64 = 0x0101010101010101;
+const LOW_BITS: u64 = ONES * 0x7f;
+const HIGH_BITS: u64 = ONES * 0x80;
+
+// how many of the eight bytes in a word are equal to b
+fn count_byte_in_word(word: u64, b: u8) -> u32 {
+    let x = word ^ (ONES * b as u64);
+
+    // the high bit of each byte ends up set unless that byte was zero
+    // and the low seven bits can't carry into the next byte
+    let nonzero = ((x & LOW_BITS) + LOW_BITS) | x;
+
+    (!nonzero & HIGH_BITS).count_ones()
+}
+
+#[test]
+fn test_count_byte_in_word() {
+    let examples: Vec<(&[u8; 8], u8, u32)> = vec![
+        (b"((((((((", b'(', 8),
+        (b"()()()()", b'(', 4),
+        (b"()()()()", b')', 4),
+        (b"\x00\x80\xff(\x7f)\x01\xa8", b'(', 1),
+        (b"\x00\x80\xff(\x7f)\x01\xa9", b')', 1),
+        (b"\x00\x00\x00\x00\x00\x00\x00\x00", 0, 8),
+        (b"\xff\xff\xff\xff\xff\xff\xff\xff", 0xff, 8),
+    ];
+    for (bytes, b, count) in examples.into_iter() {
+        let mut word = [0; 8];
+        word.copy_from_slice(bytes);
+        assert_eq!( count_byte_in_word(u64::from_le_bytes(word), b), count );
+    }
+}
+
+// numbers of opening and closing parentheses, a word at a time
+fn count_parens(bytes: &[u8]) -> (u64, u64) {
+    let mut opens = 0;
+    let mut closes = 0;
+
+    let words = bytes.chunks_exact(8);
+    let rest = words.remainder();
+
+    for chunk in words {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        let word = u64::from_le_bytes(word);
+
+        opens += count_byte_in_word(word, b'(') as u64;
+        closes += count_byte_in_word(word, b')') as u64;
+    }
+
+    for &b in rest {
+        opens += (b == b'(') as u64;
+        closes += (b == b')') as u64;
+    }
+
+    (opens, closes)
+}
+
+#[test]
+fn test_count_parens() {
+    let examples = [
+        ("", (0, 0)),
+        ("(", (1, 0)),
+        ("(())x", (2, 2)),
+        ("((((((((())))))))))", (9, 10)),
+        ("(\u{e9}()))(())(((\n", (7, 5)),
+    ];
+    for &(s, v) in examples.iter() {
+        assert_eq!( count_parens(s.as_bytes()), v );
+    }
+}
+
+// keeps a running total over input fed to it in chunks
+// so the whole of it never needs to be in memory at once
+struct FloorCounter {
+    opens: u64,
+    closes: u64,
+    length: u64,
+    first_invalid: Option<InvalidChar>,
+    last: Option<u8>,
+}
+
+impl FloorCounter {
+    fn new() -> FloorCounter {
+        FloorCounter {
+            opens: 0,
+            closes: 0,
+            length: 0,
+            first_invalid: None,
+            last: None,
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8]) {
+        let (opens, closes) = count_parens(bytes);
+
+        // a character split between chunks comes out as U+FFFD
+        if self.first_invalid.is_none() && opens + closes < bytes.len() as u64 {
+            let offset = self.length as usize;
+            self.first_invalid = first_invalid(bytes)
+                .map(|e| InvalidChar { offset: offset + e.offset, ..e });
+        }
+
+        self.opens += opens;
+        self.closes += closes;
+        self.length += bytes.len() as u64;
+        self.last = bytes.last().cloned().or(self.last);
+    }
+
+    fn floor(&self) -> i64 {
+        self.opens as i64 - self.closes as i64
+    }
+
+    // the same rules as validate
+    fn validate(&self) -> Result<(), InvalidChar> {
+        let others = self.length - self.opens - self.closes;
+
+        match self.first_invalid {
+            None => Ok(()),
+            Some(ref e) if others == 1 && self.last == Some(b'\n') => {
+                debug_assert_eq!(e.offset as u64, self.length - 1);
+                Ok(())
+            }
+            Some(ref e) => Err(InvalidChar {
+                offset: e.offset,
+                character: e.character,
+            }),
+        }
+    }
+}
+
+#[test]
+fn test_floor_counter() {
+    let examples = [
+        "",
+        "(())",
+        "((((((((((((((((((((((((()\n",
+        "(()))))(((((((((()))))))))))()()()(()()()x\n",
+        "((((((((((((((((((((((((\n\n",
+        "()\u{e9}((((((((((((((((((((((((\n\n",
+    ];
+
+    for &s in examples.iter() {
+        for chunk_size in 1..10 {
+            let mut counter = FloorCounter::new();
+            for chunk in s.as_bytes().chunks(chunk_size) {
+                counter.feed(chunk);
+            }
+
+            assert_eq!( counter.floor(), str_value(s) );
+
+            // multi-byte characters only survive whole chunks
+            if chunk_size >= 4 || s.is_ascii() {
+                assert_eq!( counter.validate(), validate(s.as_bytes()) );
+            } else {
+                assert_eq!( counter.validate().is_ok(),
+                            validate(s.as_bytes()).is_ok() );
+            }
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn bench_count_parens() {
+    // cargo test --release --bin day1 -- --ignored --nocapture
+    use std::time::Instant;
+
+    // sixteen megabytes of a cheap pseudo-random walk
+    let mut state: u32 = 1;
+    let s: String = (0..16 << 20).map(|_| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        if state & 0x10000 == 0 { '(' } else { ')' }
+    }).collect();
+
+    let start = Instant::now();
+    let expected = str_value(&s);
+    let by_chars = start.elapsed();
+
+    let start = Instant::now();
+    let mut counter = FloorCounter::new();
+    for chunk in s.as_bytes().chunks(64 << 10) {
+        counter.feed(chunk);
+    }
+    let by_words = start.elapsed();
+
+    assert_eq!(counter.floor(), expected);
+    println!("str_value: {:?}", by_chars);
+    println!("FloorCounter: {:?}", by_words);
+}
+
+enum Query {
+    Floor,
+    Basement,
+    Target(i64),
+    Report,
+    Csv,
+}
+
+// what to report, and whether to overlook stray characters
+fn options<I: Iterator<Item=String>>(mut args: I)
+    -> Result<(Query, bool), String>
+{
+    // the final floor, unless asked when santa first reaches one
+    let mut query = Query::Floor;
+    let mut lenient = false;
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--basement" => { query = Query::Basement; }
+            "--report" => { query = Query::Report; }
+            "--csv" => { query = Query::Csv; }
+            "--lenient" => { lenient = true; }
+            "--target" => {
+                query = Query::Target(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or("--target needs a floor")?);
+            }
+            _ => { return Err(format!("unknown argument: {}", arg)); }
+        }
+    }
+
+    Ok((query, lenient))
+}
+
+#[test]
+fn test_options() {
+    let args = |s: &'static str| s.split_whitespace().map(String::from);
+
+    assert!(matches!(options(args("--target -3")),
+                     Ok((Query::Target(-3), false))));
+    assert!(matches!(options(args("--lenient --csv")),
+                     Ok((Query::Csv, true))));
+
+    let bad_examples = [
+        "--target",
+        "--target up",
+        "--frobnicate",
+    ];
+
+    for &s in bad_examples.iter() {
+        assert!(options(args(s)).is_err());
+    }
+}
+
+fn answer(query: &Query, input: &[u8], lenient: bool)
+    -> Result<String, InvalidChar>
+{
+    // anything but parentheses used to count as staying put
+    // now it's an error unless asked to overlook it
+    if !lenient {
+        validate(input)?;
+    }
+
+    // only the parentheses matter, so whatever lenient mode let through
+    // can be replaced if it isn't utf-8
+    let s = String::from_utf8_lossy(strip_newline(input));
+
+    let position = match *query {
+        Query::Floor => { return Ok(format!("{}\n", str_value(&s))); }
+        Query::Report => { return Ok(trajectory_report(&s)); }
+        Query::Csv => { return Ok(trajectory_csv(&s)); }
+        Query::Basement => basement_position(&s),
+        Query::Target(target) => first_position_reaching(&s, target),
+    };
+
+    Ok(match position {
+        Some(p) => format!("{}\n", p),
+        None => String::from("never\n"),
+    })
+}
+
+#[test]
+fn test_answer() {
+    let examples: Vec<(Query, &[u8], bool, &str)> = vec![
+        (Query::Floor, b"(()\n", false, "1\n"),
+        (Query::Basement, b"())\n", false, "3\n"),
+        (Query::Target(2), b"()\n", false, "never\n"),
+        (Query::Csv, b"(()\n", false, "step,floor\n0,0\n1,1\n2,2\n3,1\n"),
+        (Query::Csv, b"(x\xff\n", true, "step,floor\n0,0\n1,1\n2,1\n3,1\n"),
+        (Query::Report, b"(()\n", false, concat!(
+            "final floor: 1\n",
+            "lowest floor: 0\n",
+            "highest floor: 2\n",
+            "floor  steps  crossings\n",
+            "    0      1          0\n",
+            "    1      2          1\n",
+            "    2      1          0\n",
+        )),
+    ];
+    for (query, input, lenient, output) in examples.into_iter() {
+        assert_eq!( answer(&query, input, lenient), Ok(String::from(output)) );
+    }
+
+    let invalid = InvalidChar { offset: 2, character: '\n' };
+    assert_eq!( answer(&Query::Csv, b"((\n\n", false), Err(invalid) );
+}
+
 fn main() {
-    let mut s = String::new();
-    io::stdin().read_to_string(&mut s).unwrap();
-    println!("{}", str_value(&s));
+    let (query, lenient) = match options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    let result = if let Query::Floor = query {
+        // the final floor can be counted as the input streams past
+        // without keeping any of it
+        let mut buf = vec![0; 64 << 10];
+        let mut counter = FloorCounter::new();
+
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => counter.feed(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+
+        match counter.validate() {
+            Err(e) if !lenient => Err(e),
+            _ => Ok(format!("{}\n", counter.floor())),
+        }
+    } else {
+        let mut input = Vec::new();
+        if let Err(e) = stdin.read_to_end(&mut input) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+
+        answer(&query, &input, lenient)
+    };
+
+    match result {
+        Ok(answer) => print!("{}", answer),
+        Err(e) => {
+            eprintln!("unexpected {:?} at offset {}", e.character, e.offset);
+            process::exit(1);
+        }
+    }
 }
 
 #[test]
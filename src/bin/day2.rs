@@ -1,80 +1,618 @@
+use std::cmp::Reverse;
+use std::env;
+use std::fmt;
 use std::io::{self, BufRead};
+use std::process;
+use std::str;
+use std::str::FromStr;
 
-fn wrapping_paper_for_box(box_dimensions: [i32; 3]) -> i32 {
-    let w = box_dimensions[0];
-    let l = box_dimensions[1];
-    let h = box_dimensions[2];
+// shadows the standard Box, which this file has no use for
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Box {
+    l: u64,
+    w: u64,
+    h: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseBoxError {
+    WrongNumberOfDimensions(usize),
+    InvalidNumber(String),
+    ZeroDimension,
+    NegativeDimension(i64),
+    InvalidUtf8,
+}
+
+impl fmt::Display for ParseBoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseBoxError::WrongNumberOfDimensions(n) =>
+                write!(f, "expected 3 dimensions, found {}", n),
+            ParseBoxError::InvalidNumber(ref e) =>
+                write!(f, "invalid dimension: {}", e),
+            ParseBoxError::ZeroDimension =>
+                write!(f, "dimensions must not be zero"),
+            ParseBoxError::NegativeDimension(d) =>
+                write!(f, "dimensions must not be negative: {}", d),
+            ParseBoxError::InvalidUtf8 => write!(f, "invalid utf-8"),
+        }
+    }
+}
+
+// a result too large for 64 bits
+#[derive(Debug, PartialEq)]
+struct Overflow;
 
-    let wl = w * l;
-    let wh = w * h;
-    let lh = l * h;
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "arithmetic overflow")
+    }
+}
 
-    let extra = vec![wl, wh, lh].into_iter().min().unwrap();
+fn add(a: u64, b: u64) -> Result<u64, Overflow> {
+    a.checked_add(b).ok_or(Overflow)
+}
 
-    2 * (wl + wh + lh) + extra
+fn mul(a: u64, b: u64) -> Result<u64, Overflow> {
+    a.checked_mul(b).ok_or(Overflow)
+}
+
+impl Box {
+    fn new(l: i64, w: i64, h: i64) -> Result<Box, ParseBoxError> {
+        for &d in [l, w, h].iter() {
+            if d == 0 { return Err(ParseBoxError::ZeroDimension); }
+            if d < 0 { return Err(ParseBoxError::NegativeDimension(d)); }
+        }
+
+        Ok(Box { l: l as u64, w: w as u64, h: h as u64 })
+    }
+
+    fn faces(&self) -> Result<[u64; 3], Overflow> {
+        Ok([mul(self.l, self.w)?, mul(self.w, self.h)?, mul(self.h, self.l)?])
+    }
+
+    fn surface_area(&self) -> Result<u64, Overflow> {
+        let faces = self.faces()?;
+        mul(2, add(add(faces[0], faces[1])?, faces[2])?)
+    }
+
+    fn volume(&self) -> Result<u64, Overflow> {
+        mul(mul(self.l, self.w)?, self.h)
+    }
+
+    fn smallest_face(&self) -> Result<u64, Overflow> {
+        let mut sides = [self.l, self.w, self.h];
+        sides.sort();
+        mul(sides[0], sides[1])
+    }
+
+    fn smallest_perimeter(&self) -> Result<u64, Overflow> {
+        let mut sides = [self.l, self.w, self.h];
+        sides.sort();
+        mul(2, add(sides[0], sides[1])?)
+    }
+}
+
+impl FromStr for Box {
+    type Err = ParseBoxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let result: Result<Vec<i64>, _> =
+            s.split('x').map(str::parse).collect();
+
+        let vec = result
+            .map_err(|e| ParseBoxError::InvalidNumber(e.to_string()))?;
+
+        if vec.len() != 3 {
+            return Err(ParseBoxError::WrongNumberOfDimensions(vec.len()));
+        }
+
+        Box::new(vec[0], vec[1], vec[2])
+    }
+}
+
+#[test]
+fn test_box() {
+    // (box, surface area, volume, smallest face, smallest perimeter)
+    let examples = [
+        ((2, 3, 4), 52, 24, 6, 10),
+        ((1, 1, 10), 42, 10, 1, 4),
+        ((4, 3, 2), 52, 24, 6, 10),
+        ((7, 7, 7), 294, 343, 49, 28),
+    ];
+
+    for &((l, w, h), area, volume, face, perimeter) in examples.iter() {
+        let b = Box::new(l, w, h).unwrap();
+        assert_eq!( b.surface_area(), Ok(area) );
+        assert_eq!( b.volume(), Ok(volume) );
+        assert_eq!( b.smallest_face(), Ok(face) );
+        assert_eq!( b.smallest_perimeter(), Ok(perimeter) );
+    }
+}
+
+fn wrapping_paper_for_box(b: &Box) -> Result<u64, Overflow> {
+    add(b.surface_area()?, b.smallest_face()?)
 }
 
 #[test]
 fn test_wrapping_paper_for_box() {
     let examples = [
-        ([2, 3, 4], 58),
-        ([1, 1, 10], 43),
+        ("2x3x4", Ok(58)),
+        ("1x1x10", Ok(43)),
+        ("4294967296x4294967296x1", Err(Overflow)),
+        ("3037000500x3037000500x1", Err(Overflow)),
+        ("1073741824x1073741824x1", Ok(2305843014582403072)),
     ];
 
-    for &(b, w) in examples.iter() {
-        assert_eq!( wrapping_paper_for_box(b), w );
+    for &(b, ref w) in examples.iter() {
+        assert_eq!( &wrapping_paper_for_box(&b.parse().unwrap()), w );
     }
 }
 
-fn parse_box_dimensions(s: &str) -> Result<[i32; 3], String> {
-    let result: Result<Vec<i32>, _> =
-        s.split('x').map(str::parse).collect();
+fn ribbon_for_box(b: &Box) -> Result<u64, Overflow> {
+    // wrapped around the two shortest sides, plus a bow
+    add(b.smallest_perimeter()?, b.volume()?)
+}
 
-    match result {
-        Ok(vec) => {
-            if vec.len() != 3 {
-                Err(String::from("wrong number of dimensions"))
-            }
-            else { Ok([vec[0], vec[1], vec[2]]) }
-        }
-        Err(e) => Err(e.to_string()),
+#[test]
+fn test_ribbon_for_box() {
+    let examples = [
+        ("2x3x4", Ok(34)),
+        ("1x1x10", Ok(14)),
+        ("4x3x2", Ok(34)),
+        ("10x1x1", Ok(14)),
+        ("4294967296x4294967296x1", Err(Overflow)),
+        ("9223372036854775807x1x1", Ok(9223372036854775811)),
+        ("9223372036854775807x2x1", Err(Overflow)),
+    ];
+
+    for &(b, ref r) in examples.iter() {
+        assert_eq!( &ribbon_for_box(&b.parse().unwrap()), r );
+    }
+}
+
+fn totals<'a, I>(boxes: I) -> Result<(u64, u64), Overflow> where
+    I: IntoIterator<Item=&'a Box>,
+{
+    let mut paper = 0;
+    let mut ribbon = 0;
+
+    for b in boxes {
+        paper = add(paper, wrapping_paper_for_box(b)?)?;
+        ribbon = add(ribbon, ribbon_for_box(b)?)?;
+    }
+
+    Ok((paper, ribbon))
+}
+
+// the same formulas, computed wide enough that they can't overflow
+#[cfg(test)]
+fn reference(b: &Box) -> (u128, u128) {
+    let mut sides = [b.l as u128, b.w as u128, b.h as u128];
+    sides.sort();
+    let (a, b, c) = (sides[0], sides[1], sides[2]);
+
+    let paper = 2 * (a * b + b * c + c * a) + a * b;
+    let ribbon = 2 * (a + b) + a * b * c;
+
+    (paper, ribbon)
+}
+
+#[cfg(test)]
+fn checked(reference: u128) -> Result<u64, Overflow> {
+    if reference > u64::MAX as u128 { Err(Overflow) }
+    else { Ok(reference as u64) }
+}
+
+// xorshift, so the property tests below are repeatable
+#[cfg(test)]
+struct Rng(u64);
+
+#[cfg(test)]
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // dimensions of every magnitude, so both sides of overflow get hit
+    // but no wider than 42 bits, so the reference can't overflow either
+    fn dimension(&mut self) -> i64 {
+        let bits = self.next() % 42 + 1;
+        let d = (self.next() >> (64 - bits)) as i64;
+        if d == 0 { 1 } else { d }
+    }
+
+    fn boxed(&mut self) -> Box {
+        Box::new(self.dimension(), self.dimension(), self.dimension())
+            .unwrap()
+    }
+}
+
+#[test]
+fn test_against_reference() {
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+
+    for _ in 0..100000 {
+        let b = rng.boxed();
+        let (paper, ribbon) = reference(&b);
+
+        assert_eq!( wrapping_paper_for_box(&b), checked(paper), "{:?}", b );
+        assert_eq!( ribbon_for_box(&b), checked(ribbon), "{:?}", b );
     }
 }
 
 #[test]
-fn test_parse_box_dimensions() {
+fn test_totals_against_reference() {
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+
+    for n in 0..1000 {
+        // small enough boxes that some totals fit and some don't
+        let boxes: Vec<Box> = (0..n % 50).map(|_| loop {
+            let b = rng.boxed();
+            if b.l < 1 << 25 && b.w < 1 << 25 && b.h < 1 << 25 { break b; }
+        }).collect();
+
+        let (paper, ribbon) = boxes.iter().map(reference)
+            .fold((0, 0), |(p, r), (bp, br)| (p + bp, r + br));
+
+        let expected = checked(paper)
+            .and_then(|paper| checked(ribbon).map(|ribbon| (paper, ribbon)));
+
+        assert_eq!( totals(&boxes), expected );
+    }
+}
+
+#[test]
+fn test_parse_box() {
     let examples = [
-        ("2x3x4", [2, 3, 4]),
-        ("1x1x10", [1, 1, 10]),
+        ("2x3x4", Box { l: 2, w: 3, h: 4 }),
+        ("1x1x10", Box { l: 1, w: 1, h: 10 }),
     ];
     let bad_examples = [
-        "hello!",
-        "1x1",
-        "1x2x3x4",
+        ("1x1", ParseBoxError::WrongNumberOfDimensions(2)),
+        ("1x2x3x4", ParseBoxError::WrongNumberOfDimensions(4)),
+        ("0x1x2", ParseBoxError::ZeroDimension),
+        ("1x2x0", ParseBoxError::ZeroDimension),
+        ("-1x2x3", ParseBoxError::NegativeDimension(-1)),
+        ("1x-2x3", ParseBoxError::NegativeDimension(-2)),
     ];
 
     for &(s, v) in examples.iter() {
-        assert_eq!( parse_box_dimensions(s), Ok(v) );
+        assert_eq!( s.parse(), Ok(v) );
+    }
+
+    for &(s, ref e) in bad_examples.iter() {
+        assert_eq!( s.parse::<Box>().as_ref(), Err(e) );
+    }
+
+    match "hello!".parse::<Box>() {
+        Err(ParseBoxError::InvalidNumber(_)) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+// a piece of paper to be cut from the roll,
+// width across the roll and height along it
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rect {
+    width: u64,
+    height: u64,
+}
+
+impl Box {
+    // all six faces plus the slack, which is as big as the smallest face
+    fn pieces(&self) -> [Rect; 7] {
+        let mut sides = [self.l, self.w, self.h];
+        sides.sort();
+        let (a, b, c) = (sides[0], sides[1], sides[2]);
+
+        let ab = Rect { width: b, height: a };
+        let bc = Rect { width: c, height: b };
+        let ac = Rect { width: c, height: a };
+
+        [ab, ab, bc, bc, ac, ac, ab]
+    }
+}
+
+#[test]
+fn test_pieces() {
+    let mut rng = Rng(0x853c49e6748fea9b);
+
+    for _ in 0..1000 {
+        let b = rng.boxed();
+        if let Ok(paper) = wrapping_paper_for_box(&b) {
+            let area = b.pieces().iter()
+                .map(|r| r.width as u128 * r.height as u128)
+                .sum::<u128>();
+            assert_eq!( area, paper as u128 );
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum PackError {
+    // a piece that won't fit across the roll whichever way it's turned
+    TooWide(Rect),
+    Overflow,
+}
+
+impl From<Overflow> for PackError {
+    fn from(_: Overflow) -> PackError {
+        PackError::Overflow
+    }
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackError::TooWide(r) =>
+                write!(f, "a {}x{} piece is wider than the roll",
+                    r.width, r.height),
+            PackError::Overflow => write!(f, "{}", Overflow),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Packing {
+    roll_width: u64,
+    roll_length: u64,
+    shelves: usize,
+    // paper actually used, which is the total from wrapping_paper_for_box
+    area: u64,
+}
+
+impl Packing {
+    // the shortest the roll could be if pieces could be cut up freely
+    fn lower_bound(&self) -> u64 {
+        self.area.div_ceil(self.roll_width)
     }
 
-    for &s in bad_examples.iter() {
-        assert!( parse_box_dimensions(s).is_err() );
+    fn waste(&self) -> u64 {
+        self.roll_width * self.roll_length - self.area
     }
 }
 
+// first fit decreasing height: the tallest pieces go first,
+// each onto the first shelf across the roll with room left for it
+fn pack<'a, I>(boxes: I, roll_width: u64) -> Result<Packing, PackError> where
+    I: IntoIterator<Item=&'a Box>,
+{
+    let mut pieces = vec![];
+    let mut area = 0;
+
+    for b in boxes {
+        for &r in b.pieces().iter() {
+            // lie flat if possible, to keep shelves low
+            let r = if r.width <= roll_width { r }
+                else if r.height <= roll_width {
+                    Rect { width: r.height, height: r.width }
+                }
+                else { return Err(PackError::TooWide(r)); };
+
+            area = add(area, mul(r.width, r.height)?)?;
+            pieces.push(r);
+        }
+    }
+
+    pieces.sort_by_key(|r| Reverse((r.height, r.width)));
+
+    // how much width each shelf has left
+    let mut shelves: Vec<u64> = vec![];
+    let mut roll_length = 0;
+
+    for r in pieces {
+        match shelves.iter().position(|&left| left >= r.width) {
+            Some(i) => shelves[i] -= r.width,
+            None => {
+                shelves.push(roll_width - r.width);
+                roll_length = add(roll_length, r.height)?;
+            }
+        }
+    }
+
+    // the waste has to be countable too
+    mul(roll_width, roll_length)?;
+
+    Ok(Packing {
+        roll_width,
+        roll_length,
+        shelves: shelves.len(),
+        area,
+    })
+}
+
+#[test]
+fn test_pack() {
+    let boxes = |s: &str| -> Vec<Box> {
+        s.split(' ').map(|b| b.parse().unwrap()).collect()
+    };
+
+    // (boxes, roll width, roll length, shelves, waste)
+    let examples = [
+        ("1x1x1", 7, 1, 1, 0),
+        ("1x1x1", 3, 3, 3, 2),
+        ("2x3x4", 4, 16, 7, 6),
+        ("2x3x4", 8, 9, 4, 14),
+        ("2x3x4 1x1x10", 10, 11, 7, 9),
+    ];
+
+    for &(s, width, length, shelves, waste) in examples.iter() {
+        let packing = pack(&boxes(s), width).unwrap();
+        assert_eq!( packing.roll_length, length );
+        assert_eq!( packing.shelves, shelves );
+        assert_eq!( packing.waste(), waste );
+    }
+
+    // as wide a roll as can be counted
+    let packing = pack(&boxes("1x1x1"), u64::MAX).unwrap();
+    assert_eq!( packing.roll_length, 1 );
+    assert_eq!( packing.lower_bound(), 1 );
+    assert_eq!( packing.waste(), u64::MAX - 7 );
+
+    assert_eq!( pack(&boxes("2x3x4"), 2),
+        Err(PackError::TooWide(Rect { width: 4, height: 3 })) );
+}
+
+#[test]
+fn test_pack_against_lower_bound() {
+    let mut rng = Rng(0xda3e39cb94b95bdb);
+
+    for n in 1..200 {
+        let boxes: Vec<Box> = (0..n).map(|_| Box::new(
+            (rng.next() % 20 + 1) as i64,
+            (rng.next() % 20 + 1) as i64,
+            (rng.next() % 20 + 1) as i64,
+        ).unwrap()).collect();
+        let roll_width = rng.next() % 100 + 20;
+
+        let packing = pack(&boxes, roll_width).unwrap();
+        assert_eq!( packing.area, totals(&boxes).unwrap().0 );
+        assert!( packing.roll_length >= packing.lower_bound() );
+        assert!( packing.shelves <= 7 * n );
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct BadLine {
+    // counted from 1, the way editors do
+    number: usize,
+    // as near the original as utf-8 allows
+    text: String,
+    error: ParseBoxError,
+}
+
+impl fmt::Display for BadLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {:?}: {}", self.number, self.text, self.error)
+    }
+}
+
+// every box that parses, and every line that doesn't
+// lines are read as bytes so that invalid utf-8 is just one more bad line
+fn parse_boxes<R: BufRead>(reader: R) -> io::Result<(Vec<Box>, Vec<BadLine>)> {
+    let mut boxes = vec![];
+    let mut bad_lines = vec![];
+
+    for (i, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        let parsed = str::from_utf8(line)
+            .map_err(|_| ParseBoxError::InvalidUtf8)
+            .and_then(str::parse);
+
+        match parsed {
+            Ok(b) => boxes.push(b),
+            Err(e) => bad_lines.push(BadLine {
+                number: i + 1,
+                text: String::from_utf8_lossy(line).into_owned(),
+                error: e,
+            }),
+        }
+    }
+
+    Ok((boxes, bad_lines))
+}
+
+#[test]
+fn test_parse_boxes() {
+    let input: &[u8] = b"2x3x4\n1x1\r\n1x1x10\n\n0x1x2\n\xff\n";
+    let (boxes, bad_lines) = parse_boxes(input).unwrap();
+
+    assert_eq!( boxes, vec![
+        Box { l: 2, w: 3, h: 4 },
+        Box { l: 1, w: 1, h: 10 },
+    ]);
+
+    let bad_lines: Vec<_> = bad_lines.iter()
+        .map(|bad| (bad.number, &*bad.text)).collect();
+    assert_eq!( bad_lines, vec![
+        (2, "1x1"), (4, ""), (5, "0x1x2"), (6, "\u{fffd}"),
+    ]);
+}
+
+struct Options {
+    // bad lines are fatal unless asked to total up the good ones anyway
+    lenient: bool,
+    roll_width: Option<u64>,
+}
+
+fn options<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options { lenient: false, roll_width: None };
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--lenient" => { options.lenient = true; }
+            "--roll" => {
+                options.roll_width = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .ok_or("--roll needs a positive width")?);
+            }
+            _ => { return Err(format!("unknown argument: {}", arg)); }
+        }
+    }
+
+    Ok(options)
+}
+
 fn main() {
+    let options = match options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
     let stdin = io::stdin();
-    let lines: Result<Vec<_>, _> = stdin.lock().lines().collect();
-    let box_dimensions: Result<Vec<_>, _> = lines.unwrap().iter()
-        .map(|d| parse_box_dimensions(&d)).collect();
-
-    match box_dimensions {
-        Err(e) => { println!("{}", e); }
-        Ok(box_dimensions) => {
-            let total_wrapping_paper = box_dimensions.into_iter()
-                .map(wrapping_paper_for_box)
-                .fold(0, |acc, i| acc + i);
-            println!("{}", total_wrapping_paper);
+    let (boxes, bad_lines) = match parse_boxes(stdin.lock()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
+    };
+
+    for bad in bad_lines.iter() {
+        eprintln!("{}", bad);
+    }
+
+    if !options.lenient && !bad_lines.is_empty() {
+        process::exit(1);
+    }
+
+    match totals(&boxes) {
+        Ok((paper, ribbon)) => {
+            println!("{}", paper);
+            println!("{}", ribbon);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(roll_width) = options.roll_width {
+        match pack(&boxes, roll_width) {
+            Ok(packing) => {
+                println!("roll length: {}", packing.roll_length);
+                println!("lower bound: {}", packing.lower_bound());
+                println!("shelves: {}", packing.shelves);
+                println!("waste: {}", packing.waste());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // even a lenient total is no good if it left lines out
+    if !bad_lines.is_empty() {
+        process::exit(1);
     }
 }
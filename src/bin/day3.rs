@@ -1,61 +1,227 @@
-use std::io::{self, Read};
-use std::collections::HashSet;
+use std::env;
+use std::io::{self, BufRead};
+use std::iter::FromIterator;
+use std::ops::Range;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::process;
+use std::str;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Direction {
     North,
     South,
     East,
     West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+}
+
+// flat-topped hexagons, so there is no due east or west
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HexDirection {
+    North,
+    South,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
 }
 
+// square grids use (x, y)
+// hex grids use axial (q, r) with r growing to the north
 type Location = (i32, i32);
 
-struct HousePath<I: Iterator<Item=Direction>> {
-    location: Option<Location>,
+trait Step {
+    fn step(&self, location: Location) -> Location;
+    fn opposite(&self) -> Self;
+    // the glyph the parser reads this direction from
+    fn glyph(&self) -> char;
+}
+
+impl Step for Direction {
+    fn step(&self, (x, y): Location) -> Location {
+        match *self {
+            Direction::North => (x, y + 1),
+            Direction::South => (x, y - 1),
+            Direction::East => (x + 1, y),
+            Direction::West => (x - 1, y),
+            Direction::NorthEast => (x + 1, y + 1),
+            Direction::NorthWest => (x - 1, y + 1),
+            Direction::SouthEast => (x + 1, y - 1),
+            Direction::SouthWest => (x - 1, y - 1),
+        }
+    }
+
+    fn opposite(&self) -> Direction {
+        match *self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+            Direction::NorthEast => Direction::SouthWest,
+            Direction::NorthWest => Direction::SouthEast,
+            Direction::SouthEast => Direction::NorthWest,
+            Direction::SouthWest => Direction::NorthEast,
+        }
+    }
+
+    fn glyph(&self) -> char {
+        match *self {
+            Direction::North => '^',
+            Direction::South => 'v',
+            Direction::East => '>',
+            Direction::West => '<',
+            Direction::NorthEast => 'e',
+            Direction::NorthWest => 'q',
+            Direction::SouthEast => 'c',
+            Direction::SouthWest => 'z',
+        }
+    }
+}
+
+impl Step for HexDirection {
+    fn step(&self, (q, r): Location) -> Location {
+        match *self {
+            HexDirection::North => (q, r + 1),
+            HexDirection::South => (q, r - 1),
+            HexDirection::NorthEast => (q + 1, r),
+            HexDirection::NorthWest => (q - 1, r + 1),
+            HexDirection::SouthEast => (q + 1, r - 1),
+            HexDirection::SouthWest => (q - 1, r),
+        }
+    }
+
+    fn opposite(&self) -> HexDirection {
+        match *self {
+            HexDirection::North => HexDirection::South,
+            HexDirection::South => HexDirection::North,
+            HexDirection::NorthEast => HexDirection::SouthWest,
+            HexDirection::NorthWest => HexDirection::SouthEast,
+            HexDirection::SouthEast => HexDirection::NorthWest,
+            HexDirection::SouthWest => HexDirection::NorthEast,
+        }
+    }
+
+    fn glyph(&self) -> char {
+        match *self {
+            HexDirection::North => '^',
+            HexDirection::South => 'v',
+            HexDirection::NorthEast => 'e',
+            HexDirection::NorthWest => 'q',
+            HexDirection::SouthEast => 'c',
+            HexDirection::SouthWest => 'z',
+        }
+    }
+}
+
+#[test]
+fn test_step() {
+    // going there and back again should be a no-op
+    let there_and_back = vec![
+        (Direction::North, Direction::South),
+        (Direction::East, Direction::West),
+        (Direction::NorthEast, Direction::SouthWest),
+        (Direction::NorthWest, Direction::SouthEast),
+    ];
+    for (there, back) in there_and_back.into_iter() {
+        assert_eq!(back.step(there.step((3, 4))), (3, 4));
+        assert_eq!(there.opposite(), back);
+        assert_eq!(back.opposite(), there);
+        assert_eq!(parser(there.glyph()), Some(there));
+        assert_eq!(parser(back.glyph()), Some(back));
+    }
+
+    let hex_there_and_back = vec![
+        (HexDirection::North, HexDirection::South),
+        (HexDirection::NorthEast, HexDirection::SouthWest),
+        (HexDirection::NorthWest, HexDirection::SouthEast),
+    ];
+    for (there, back) in hex_there_and_back.into_iter() {
+        assert_eq!(back.step(there.step((3, 4))), (3, 4));
+        assert_eq!(there.opposite(), back);
+        assert_eq!(back.opposite(), there);
+        assert_eq!(hex_parser(there.glyph()), Some(there));
+        assert_eq!(hex_parser(back.glyph()), Some(back));
+    }
+}
+
+struct HousePath<I: Iterator> where I::Item: Step {
+    start: Option<Location>,
+    walkers: Vec<Location>,
+    turn: usize,
     directions: I,
 }
 
-impl<I: Iterator<Item=Direction>> HousePath<I> {
+impl<I: Iterator> HousePath<I> where I::Item: Step {
     fn from_directions<U>(directions: U) -> Self where
-        U: IntoIterator<IntoIter=I,Item=Direction>
+        U: IntoIterator<IntoIter=I,Item=I::Item>
     {
         HousePath {
-            location: Some((0, 0)),
+            start: Some((0, 0)),
+            walkers: vec![(0, 0)],
+            turn: 0,
             directions: directions.into_iter(),
         }
     }
+
+    // directions are dealt out to the walkers in turn
+    fn with_walkers(mut self, walkers: usize) -> Self {
+        assert!(walkers > 0, "a house path needs at least one walker");
+
+        self.walkers = vec![(0, 0); walkers];
+        self
+    }
+
+    // pairs each location with the walker who stepped there
+    // the shared start goes to the first walker
+    fn turns(self) -> Turns<I> {
+        Turns { path: self }
+    }
 }
 
-impl<I: Iterator<Item=Direction>> Iterator for HousePath<I> {
+impl<I: Iterator> Iterator for HousePath<I> where I::Item: Step {
     type Item = Location;
 
     fn next(&mut self) -> Option<Location> {
-        // fetch current location
+        // everyone shares the starting location
+        // so it is only reported once
+        if let Some(start) = self.start.take() {
+            return Some(start);
+        }
+
         // short-circuit if we're already done
-        let location = match self.location {
-            Some(location) => location,
+        let direction = match self.directions.next() {
+            Some(direction) => direction,
             None => { return None; },
         };
 
-        // calculate next location
-        self.location = match self.directions.next() {
-            Some(Direction::North) =>
-                Some((location.0, location.1 + 1)),
-            Some(Direction::South) =>
-                Some((location.0, location.1 - 1)),
-            Some(Direction::East) =>
-                Some((location.0 + 1, location.1)),
-            Some(Direction::West) =>
-                Some((location.0 - 1, location.1)),
-            None => None,
-        };
+        // move whoever's turn it is
+        let location = direction.step(self.walkers[self.turn]);
+
+        self.walkers[self.turn] = location;
+        self.turn = (self.turn + 1) % self.walkers.len();
 
-        // return current location
         Some(location)
     }
 }
 
+struct Turns<I: Iterator> where I::Item: Step {
+    path: HousePath<I>,
+}
+
+impl<I: Iterator> Iterator for Turns<I> where I::Item: Step {
+    type Item = (usize, Location);
+
+    fn next(&mut self) -> Option<(usize, Location)> {
+        let walker = self.path.turn;
+
+        self.path.next().map(|location| (walker, location))
+    }
+}
+
 #[test]
 fn test_iterator_for_house_path() {
     let examples: Vec<(Vec<Direction>, Vec<Location>)> = vec![
@@ -87,12 +253,59 @@ fn test_iterator_for_house_path() {
     }
 }
 
+#[test]
+fn test_house_path_on_hex_grid() {
+    // a ring around the house at (1, 0)
+    let directions = "^ecvzq".chars().filter_map(hex_parser);
+    let locations: Vec<_> = HousePath::from_directions(directions).collect();
+
+    assert_eq!(
+        locations,
+        vec![(0, 0), (0, 1), (1, 1), (2, 0), (2, -1), (1, -1), (0, 0)],
+    );
+}
+
+#[test]
+fn test_house_path_with_walkers() {
+    let examples: Vec<(Vec<Direction>, usize, Vec<Location>)> = vec![
+        (
+            vec![Direction::North, Direction::South],
+            2,
+            vec![(0, 0), (0, 1), (0, -1)],
+        ),
+        (
+            vec![Direction::North, Direction::East,
+                 Direction::South, Direction::West],
+            2,
+            vec![(0, 0), (0, 1), (1, 0), (0, 0), (0, 0)],
+        ),
+        (
+            vec![Direction::North, Direction::East,
+                 Direction::South, Direction::West],
+            3,
+            vec![(0, 0), (0, 1), (1, 0), (0, -1), (-1, 1)],
+        ),
+    ];
+
+    for (directions, walkers, reference_locations) in examples.into_iter() {
+        let house_path = HousePath::from_directions(directions)
+            .with_walkers(walkers);
+
+        let locations: Vec<_> = house_path.collect();
+        assert_eq!(locations, reference_locations);
+    }
+}
+
 fn parser(c: char) -> Option<Direction> {
     match c {
         '^' => Some(Direction::North),
         'v' => Some(Direction::South),
         '>' => Some(Direction::East),
         '<' => Some(Direction::West),
+        'e' => Some(Direction::NorthEast),
+        'q' => Some(Direction::NorthWest),
+        'c' => Some(Direction::SouthEast),
+        'z' => Some(Direction::SouthWest),
         _ => None
     }
 }
@@ -117,6 +330,11 @@ fn test_parser() {
                  Direction::North, Direction::South,
                  Direction::North, Direction::South],
         ),
+        (
+            "qezc",
+            vec![Direction::NorthWest, Direction::NorthEast,
+                 Direction::SouthWest, Direction::SouthEast],
+        ),
     ];
 
     for (string, reference_directions) in examples.into_iter() {
@@ -126,17 +344,1150 @@ fn test_parser() {
     }
 }
 
-fn main() {
-    let mut stdin = io::stdin();
-    let mut buf = String::new();
+// same glyphs as the square grid, minus due east and west
+fn hex_parser(c: char) -> Option<HexDirection> {
+    match c {
+        '^' => Some(HexDirection::North),
+        'v' => Some(HexDirection::South),
+        'e' => Some(HexDirection::NorthEast),
+        'q' => Some(HexDirection::NorthWest),
+        'c' => Some(HexDirection::SouthEast),
+        'z' => Some(HexDirection::SouthWest),
+        _ => None
+    }
+}
+
+#[test]
+fn test_hex_parser() {
+    let examples: Vec<(&str, Vec<HexDirection>)> = vec![
+        (
+            "^v",
+            vec![HexDirection::North, HexDirection::South],
+        ),
+        (
+            "<qez>c",
+            vec![HexDirection::NorthWest, HexDirection::NorthEast,
+                 HexDirection::SouthWest, HexDirection::SouthEast],
+        ),
+    ];
+
+    for (string, reference_directions) in examples.into_iter() {
+        let directions: Vec<HexDirection> = string.chars()
+            .filter_map(hex_parser).collect();
+        assert_eq!(directions, reference_directions);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Visit {
+    step: usize,
+    location: Location,
+    first_visit: usize,
+    // steps taken since the last time we were here
+    loop_length: Option<usize>,
+}
+
+impl Visit {
+    fn is_new(&self) -> bool {
+        self.loop_length.is_none()
+    }
+}
+
+struct Visits<I: Iterator<Item=Location>> {
+    locations: I,
+    step: usize,
+    // first and most recent step at each location
+    seen: HashMap<Location, (usize, usize)>,
+}
+
+impl<I: Iterator<Item=Location>> Visits<I> {
+    fn from_locations<U>(locations: U) -> Self where
+        U: IntoIterator<IntoIter=I,Item=Location>
+    {
+        Visits {
+            locations: locations.into_iter(),
+            step: 0,
+            seen: HashMap::new(),
+        }
+    }
+}
+
+impl<I: Iterator<Item=Location>> Iterator for Visits<I> {
+    type Item = Visit;
+
+    fn next(&mut self) -> Option<Visit> {
+        let location = match self.locations.next() {
+            Some(location) => location,
+            None => { return None; },
+        };
+        let step = self.step;
+        self.step += 1;
+
+        let visit = match self.seen.get_mut(&location) {
+            Some(&mut (first, ref mut last)) => {
+                let loop_length = step - *last;
+                *last = step;
+
+                Visit {
+                    step,
+                    location,
+                    first_visit: first,
+                    loop_length: Some(loop_length),
+                }
+            }
+            None => Visit {
+                step,
+                location,
+                first_visit: step,
+                loop_length: None,
+            },
+        };
+
+        if visit.is_new() {
+            self.seen.insert(location, (step, step));
+        }
+
+        Some(visit)
+    }
+}
+
+#[test]
+fn test_visits() {
+    let directions = "^>v<^".chars().filter_map(parser);
+    let house_path = HousePath::from_directions(directions);
+    let visits: Vec<_> = Visits::from_locations(house_path)
+        .map(|v| (v.step, v.first_visit, v.loop_length))
+        .collect();
+
+    assert_eq!(visits, vec![
+        (0, 0, None),
+        (1, 1, None),
+        (2, 2, None),
+        (3, 3, None),
+        (4, 0, Some(4)),
+        (5, 1, Some(4)),
+    ]);
+
+    let directions = "^v^".chars().filter_map(parser);
+    let house_path = HousePath::from_directions(directions);
+    let loops: Vec<_> = Visits::from_locations(house_path)
+        .map(|v| v.loop_length)
+        .collect();
+
+    assert_eq!(loops, vec![None, None, Some(2), Some(2)]);
+}
+
+fn first_revisit<I: Iterator<Item=Location>>(locations: I) -> Option<Visit> {
+    Visits::from_locations(locations).find(|v| !v.is_new())
+}
+
+#[test]
+fn test_first_revisit() {
+    let examples: Vec<(&str, Option<(usize, Location)>)> = vec![
+        ( ">>>", None ),
+        ( "^>v<", Some((4, (0, 0))) ),
+        ( ">^<^>v", Some((6, (1, 1))) ),
+    ];
+
+    for (string, reference_result) in examples.into_iter() {
+        let directions = string.chars().filter_map(parser);
+        let house_path = HousePath::from_directions(directions);
+        let result = first_revisit(house_path).map(|v| (v.step, v.location));
+
+        assert_eq!(result, reference_result);
+    }
+}
+
+// steps covered by the longest run that never repeats a house
+// the earliest one wins a tie
+fn longest_loop_free_stretch<I>(locations: I) -> Range<usize> where
+    I: Iterator<Item=Location>
+{
+    let mut longest = 0..0;
+    let mut start = 0;
+
+    for visit in Visits::from_locations(locations) {
+        if let Some(loop_length) = visit.loop_length {
+            start = start.max(visit.step - loop_length + 1);
+        }
+        if visit.step + 1 - start > longest.end - longest.start {
+            longest = start..visit.step + 1;
+        }
+    }
+
+    longest
+}
+
+#[test]
+fn test_longest_loop_free_stretch() {
+    let examples: Vec<(&str, Range<usize>)> = vec![
+        ( "", 0..1 ),
+        ( ">>>", 0..4 ),
+        ( "^>v<^", 0..4 ),
+        ( "^v^>>", 2..6 ),
+        ( "<>>>><", 1..6 ),
+    ];
+
+    for (string, reference_result) in examples.into_iter() {
+        let directions = string.chars().filter_map(parser);
+        let house_path = HousePath::from_directions(directions);
+
+        assert_eq!(longest_loop_free_stretch(house_path), reference_result);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct InvalidGlyph {
+    offset: usize,
+    glyph: char,
+}
+
+// like filter_map(parser), but anything unparseable is reported
+// except for whitespace at the very end of the route
+struct StrictDirections<I, F> {
+    glyphs: I,
+    parser: F,
+    whitespace: VecDeque<(usize, char)>,
+    resume: Option<(usize, char)>,
+}
+
+impl<D, I, F> StrictDirections<I, F> where
+    I: Iterator<Item=(usize, char)>,
+    F: Fn(char) -> Option<D>,
+{
+    fn new<U>(glyphs: U, parser: F) -> Self where
+        U: IntoIterator<IntoIter=I,Item=(usize, char)>
+    {
+        StrictDirections {
+            glyphs: glyphs.into_iter(),
+            parser,
+            whitespace: VecDeque::new(),
+            resume: None,
+        }
+    }
+}
+
+impl<D, I, F> Iterator for StrictDirections<I, F> where
+    I: Iterator<Item=(usize, char)>,
+    F: Fn(char) -> Option<D>,
+{
+    type Item = Result<D, InvalidGlyph>;
+
+    fn next(&mut self) -> Option<Result<D, InvalidGlyph>> {
+        loop {
+            // whitespace with more route after it was not trailing after all
+            if self.resume.is_some() {
+                if let Some((offset, glyph)) = self.whitespace.pop_front() {
+                    return Some(Err(InvalidGlyph { offset, glyph }));
+                }
+            }
+
+            // running out with whitespace still held back is fine
+            let (offset, glyph) =
+                match self.resume.take().or_else(|| self.glyphs.next()) {
+                    Some(next) => next,
+                    None => { return None; },
+                };
+
+            if glyph.is_whitespace() {
+                self.whitespace.push_back((offset, glyph));
+                continue;
+            }
+
+            if !self.whitespace.is_empty() {
+                self.resume = Some((offset, glyph));
+                continue;
+            }
+
+            return Some(match (self.parser)(glyph) {
+                Some(direction) => Ok(direction),
+                None => Err(InvalidGlyph { offset, glyph }),
+            });
+        }
+    }
+}
+
+#[test]
+fn test_strict_directions() {
+    let examples: Vec<(&str, Vec<Result<Direction, InvalidGlyph>>)> = vec![
+        (
+            "^>\n",
+            vec![Ok(Direction::North), Ok(Direction::East)],
+        ),
+        (
+            "^>  \n\n",
+            vec![Ok(Direction::North), Ok(Direction::East)],
+        ),
+        (
+            "^x>",
+            vec![Ok(Direction::North),
+                 Err(InvalidGlyph { offset: 1, glyph: 'x' }),
+                 Ok(Direction::East)],
+        ),
+        (
+            "^ \tv\n",
+            vec![Ok(Direction::North),
+                 Err(InvalidGlyph { offset: 1, glyph: ' ' }),
+                 Err(InvalidGlyph { offset: 2, glyph: '\t' }),
+                 Ok(Direction::South)],
+        ),
+        (
+            "\u{e9}v!",
+            vec![Err(InvalidGlyph { offset: 0, glyph: '\u{e9}' }),
+                 Ok(Direction::South),
+                 Err(InvalidGlyph { offset: 3, glyph: '!' })],
+        ),
+    ];
+
+    for (string, reference_results) in examples.into_iter() {
+        let results: Vec<_> =
+            StrictDirections::new(string.char_indices(), parser).collect();
+        assert_eq!(results, reference_results);
+    }
+}
+
+// houses are tracked one bit apiece in square chunks
+// so dense routes cost far less than a set of coordinates
+const CHUNK_SHIFT: i32 = 6;
+const CHUNK_MASK: i32 = (1 << CHUNK_SHIFT) - 1;
+
+struct VisitedSet {
+    chunks: HashMap<Location, Box<[u64; 64]>>,
+    len: usize,
+}
+
+impl VisitedSet {
+    fn new() -> VisitedSet {
+        VisitedSet { chunks: HashMap::new(), len: 0 }
+    }
+
+    // arithmetic shifts round towards negative infinity
+    // so negative coordinates land in the right chunk
+    fn split((x, y): Location) -> (Location, usize, u64) {
+        let chunk = (x >> CHUNK_SHIFT, y >> CHUNK_SHIFT);
+        let row = (y & CHUNK_MASK) as usize;
+        let bit = 1 << (x & CHUNK_MASK);
+
+        (chunk, row, bit)
+    }
+
+    fn insert(&mut self, location: Location) -> bool {
+        let (chunk, row, bit) = VisitedSet::split(location);
+        let rows = self.chunks.entry(chunk)
+            .or_insert_with(|| Box::new([0; 64]));
+
+        if rows[row] & bit != 0 {
+            return false;
+        }
+
+        rows[row] |= bit;
+        self.len += 1;
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl FromIterator<Location> for VisitedSet {
+    fn from_iter<I: IntoIterator<Item=Location>>(locations: I) -> Self {
+        let mut visited = VisitedSet::new();
+
+        for location in locations {
+            visited.insert(location);
+        }
+
+        visited
+    }
+}
+
+#[test]
+fn test_visited_set() {
+    let mut visited = VisitedSet::new();
+
+    let examples: Vec<(Location, bool)> = vec![
+        ( (0, 0), true ),
+        ( (0, 0), false ),
+        ( (-1, 0), true ),
+        ( (63, 63), true ),
+        ( (64, 63), true ),
+        ( (-64, -65), true ),
+        ( (-1, 0), false ),
+        ( (i32::MIN, i32::MAX), true ),
+    ];
+
+    for (location, reference_result) in examples.into_iter() {
+        assert_eq!(visited.insert(location), reference_result);
+        assert!(!visited.insert(location));
+    }
+
+    assert_eq!(visited.len(), 6);
+}
+
+#[test]
+fn test_visited_set_against_hash_set() {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut route = String::new();
+    File::open("data/day3.txt").unwrap().read_to_string(&mut route).unwrap();
+
+    for walkers in 1..4 {
+        let locations = || {
+            let directions = route.chars().filter_map(parser);
+            HousePath::from_directions(directions).with_walkers(walkers)
+        };
+
+        let visited: VisitedSet = locations().collect();
+        let reference: HashSet<_> = locations().collect();
+
+        assert_eq!(visited.len(), reference.len());
+    }
+}
+
+type VisitCounts = HashMap<Location, usize>;
+
+fn visit_counts<I: Iterator<Item=Location>>(locations: I) -> VisitCounts {
+    let mut counts = HashMap::new();
+
+    for location in locations {
+        *counts.entry(location).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+// ties go to the smallest location so the answer is stable
+fn most_visited(counts: &VisitCounts) -> Option<(Location, usize)> {
+    counts.iter()
+        .map(|(&location, &count)| (location, count))
+        .min_by_key(|&(location, count)| (Reverse(count), location))
+}
+
+// number of houses for each number of visits
+fn visit_distribution(counts: &VisitCounts) -> BTreeMap<usize, usize> {
+    let mut distribution = BTreeMap::new();
+
+    for &count in counts.values() {
+        *distribution.entry(count).or_insert(0) += 1;
+    }
+
+    distribution
+}
+
+fn bounding_box(counts: &VisitCounts) -> Option<(Location, Location)> {
+    counts.keys().fold(None, |acc, &(x, y)| match acc {
+        None => Some(((x, y), (x, y))),
+        Some(((x0, y0), (x1, y1))) =>
+            Some(((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y)))),
+    })
+}
+
+#[test]
+fn test_visit_counts() {
+    let directions = "^>v<^".chars().filter_map(parser);
+    let counts = visit_counts(HousePath::from_directions(directions));
+
+    assert_eq!(counts.len(), 4);
+    assert_eq!(counts[&(0, 0)], 2);
+    assert_eq!(counts[&(0, 1)], 2);
+    assert_eq!(most_visited(&counts), Some(((0, 0), 2)));
+
+    let distribution: Vec<_> = visit_distribution(&counts).into_iter()
+        .collect();
+    assert_eq!(distribution, vec![(1, 2), (2, 2)]);
+
+    assert_eq!(bounding_box(&counts), Some(((0, 0), (1, 1))));
+
+    let empty = HashMap::new();
+    assert_eq!(most_visited(&empty), None);
+    assert_eq!(bounding_box(&empty), None);
+}
+
+// rows run from north to south, one character per house
+fn render_ascii(counts: &VisitCounts) -> String {
+    let shades: Vec<char> = " .:-=+*#%@".chars().collect();
+    let levels = shades.len() - 1;
+
+    let ((x0, y0), (x1, y1)) = match bounding_box(counts) {
+        Some(bounds) => bounds,
+        None => { return String::new(); },
+    };
+    let max = most_visited(counts).map_or(1, |(_, count)| count);
+
+    let mut image = String::new();
+    for y in (y0..y1 + 1).rev() {
+        for x in x0..x1 + 1 {
+            let count = counts.get(&(x, y)).cloned().unwrap_or(0);
+            // round up so a single visit never disappears
+            let shade = (count * levels).div_ceil(max);
+            image.push(shades[shade]);
+        }
+        image.push('\n');
+    }
+
+    image
+}
+
+// the most a pgm sample can be
+const PGM_MAXVAL: usize = 65535;
+
+// plain (ascii) portable graymap, brighter means more visits
+// counts past what a sample can hold are scaled down to fit
+fn render_pgm(counts: &VisitCounts) -> String {
+    let ((x0, y0), (x1, y1)) = bounding_box(counts)
+        .unwrap_or(((0, 0), (0, 0)));
+    let max = most_visited(counts).map_or(1, |(_, count)| count);
+    let maxval = max.min(PGM_MAXVAL);
+    let sample = |count: usize| {
+        (count as u128 * maxval as u128 / max as u128) as usize
+    };
+
+    let mut image = format!("P2\n{} {}\n{}\n", x1 - x0 + 1, y1 - y0 + 1, maxval);
+    for y in (y0..y1 + 1).rev() {
+        let row: Vec<String> = (x0..x1 + 1)
+            .map(|x| counts.get(&(x, y)).cloned().unwrap_or(0))
+            .map(|count| sample(count).to_string())
+            .collect();
+        image.push_str(&row.join(" "));
+        image.push('\n');
+    }
+
+    image
+}
+
+#[test]
+fn test_render() {
+    let directions = "^>v<^>>".chars().filter_map(parser);
+    let counts = visit_counts(HousePath::from_directions(directions));
+
+    assert_eq!(render_ascii(&counts), "@@+\n@+ \n");
+    assert_eq!(render_pgm(&counts), "P2\n3 2\n2\n2 2 1\n2 1 0\n");
+
+    let empty = HashMap::new();
+    assert_eq!(render_ascii(&empty), "");
+
+    // too many visits for a pgm sample
+    let mut counts = HashMap::new();
+    counts.insert((0, 0), 131070);
+    counts.insert((1, 0), 65535);
+    counts.insert((2, 0), 1);
+    assert_eq!(render_pgm(&counts), "P2\n3 1\n65535\n65535 32767 0\n");
+}
+
+// houses are laid out on a 10 unit grid with north at the top
+// dots are drawn in location order so the output is stable
+// each walker gets a line of their own
+fn render_svg<I: Iterator<Item=(usize, Location)>>(turns: I) -> String {
+    let cell = 10;
+    let turns: Vec<_> = turns.collect();
+    let locations: Vec<_> = turns.iter().map(|&(_, l)| l).collect();
+    let counts = visit_counts(locations.iter().cloned());
+
+    let ((x0, y0), (x1, y1)) = bounding_box(&counts)
+        .unwrap_or(((0, 0), (0, 0)));
+    let max = most_visited(&counts).map_or(1, |(_, count)| count);
+    let position = |(x, y): Location| {
+        ((x - x0 + 1) * cell, (y1 - y + 1) * cell)
+    };
+
+    let mut image = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+         width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = (x1 - x0 + 2) * cell,
+        h = (y1 - y0 + 2) * cell);
+
+    let mut paths: BTreeMap<usize, Vec<Location>> = BTreeMap::new();
+    for (i, &(walker, location)) in turns.iter().enumerate() {
+        let path = paths.entry(walker).or_default();
+        // everyone leaves from the shared start, which comes first
+        if path.is_empty() && i > 0 {
+            path.push(locations[0]);
+        }
+        path.push(location);
+    }
+
+    for path in paths.values() {
+        let points: Vec<String> = path.iter()
+            .map(|&l| { let (x, y) = position(l); format!("{},{}", x, y) })
+            .collect();
+        image.push_str(&format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"gray\"/>\n",
+            points.join(" ")));
+    }
+
+    let mut houses: Vec<_> = counts.into_iter().collect();
+    houses.sort();
+    for (location, count) in houses {
+        let (x, y) = position(location);
+        // round up so a single visit still shows
+        let r = (4 * count).div_ceil(max);
+        image.push_str(&format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"black\"/>\n",
+            x, y, r));
+    }
+
+    if let Some(&start) = locations.first() {
+        let (x, y) = position(start);
+        image.push_str(&format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"5\" \
+             fill=\"none\" stroke=\"red\"/>\n",
+            x, y));
+    }
+
+    image.push_str("</svg>\n");
+    image
+}
 
-    stdin.read_to_string(&mut buf).unwrap();
+#[test]
+fn test_render_svg() {
+    let directions = "^>v<^".chars().filter_map(parser);
+    let svg = render_svg(HousePath::from_directions(directions).turns());
+
+    assert_eq!(svg, concat!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" ",
+        "width=\"30\" height=\"30\" viewBox=\"0 0 30 30\">\n",
+        "<polyline points=\"10,20 10,10 20,10 20,20 10,20 10,10\" ",
+        "fill=\"none\" stroke=\"gray\"/>\n",
+        "<circle cx=\"10\" cy=\"20\" r=\"4\" fill=\"black\"/>\n",
+        "<circle cx=\"10\" cy=\"10\" r=\"4\" fill=\"black\"/>\n",
+        "<circle cx=\"20\" cy=\"20\" r=\"2\" fill=\"black\"/>\n",
+        "<circle cx=\"20\" cy=\"10\" r=\"2\" fill=\"black\"/>\n",
+        "<circle cx=\"10\" cy=\"20\" r=\"5\" ",
+        "fill=\"none\" stroke=\"red\"/>\n",
+        "</svg>\n",
+    ));
 
-    let directions = buf.chars().filter_map(parser);
+    // the same route must always render the same way
+    let directions = "^>v<^".chars().filter_map(parser);
     let house_path = HousePath::from_directions(directions);
-    let houses: HashSet<_> = house_path.collect();
+    assert_eq!(render_svg(house_path.turns()), svg);
 
-    println!("{:?}", houses.len());
+    // santa goes up and robo-santa goes down, without lines between them
+    let directions = "^v^v".chars().filter_map(parser);
+    let house_path = HousePath::from_directions(directions).with_walkers(2);
+    let svg = render_svg(house_path.turns());
+    let polylines: Vec<_> = svg.lines()
+        .filter(|line| line.starts_with("<polyline")).collect();
+
+    assert_eq!(polylines, vec![
+        "<polyline points=\"10,30 10,20 10,10\" \
+         fill=\"none\" stroke=\"gray\"/>",
+        "<polyline points=\"10,30 10,40 10,50\" \
+         fill=\"none\" stroke=\"gray\"/>",
+    ]);
+}
+
+fn heatmap_report(counts: &VisitCounts) -> String {
+    let mut report = format!("houses: {}\n", counts.len());
+
+    if let Some(((x, y), count)) = most_visited(counts) {
+        report.push_str(&format!(
+            "most visited: ({}, {}) with {} visits\n", x, y, count));
+    }
+
+    if let Some(((x0, y0), (x1, y1))) = bounding_box(counts) {
+        report.push_str(&format!(
+            "bounding box: ({}, {}) to ({}, {})\n", x0, y0, x1, y1));
+    }
+
+    report.push_str("visits per house:\n");
+    for (visits, houses) in visit_distribution(counts) {
+        report.push_str(&format!("  {}: {}\n", visits, houses));
+    }
+
+    report
+}
+
+fn loop_report<I: Iterator<Item=Location>>(locations: I) -> String {
+    let locations: Vec<_> = locations.collect();
+    let mut report = String::new();
+
+    match first_revisit(locations.iter().cloned()) {
+        Some(visit) => {
+            report.push_str(&format!(
+                "first revisit: ({}, {}) at step {}, first seen at step {}\n",
+                visit.location.0, visit.location.1,
+                visit.step, visit.first_visit));
+        }
+        None => { report.push_str("first revisit: none\n"); }
+    }
+
+    let stretch = longest_loop_free_stretch(locations.into_iter());
+    report.push_str(&format!(
+        "longest loop-free stretch: steps {} to {} ({} houses)\n",
+        stretch.start, stretch.end - 1, stretch.end - stretch.start));
+
+    report
+}
+
+// drops steps that double straight back on themselves
+// but only where the house in between is visited some other way
+fn canonicalize<D: Step + Copy + PartialEq>(directions: &[D]) -> Vec<D> {
+    let house_path = HousePath::from_directions(directions.iter().cloned());
+    let mut counts = visit_counts(house_path);
+
+    let mut kept: Vec<D> = Vec::new();
+    let mut location = (0, 0);
+
+    for &direction in directions {
+        let next = direction.step(location);
+        let doubles_back = kept.last()
+            .is_some_and(|last| last.opposite() == direction);
+
+        if doubles_back && counts[&location] > 1 {
+            *counts.get_mut(&location).unwrap() -= 1;
+            *counts.get_mut(&next).unwrap() -= 1;
+            kept.pop();
+        } else {
+            kept.push(direction);
+        }
+
+        location = next;
+    }
+
+    kept
+}
+
+#[test]
+fn test_canonicalize() {
+    let examples: Vec<(&str, &str)> = vec![
+        ( "", "" ),
+        ( "^v", "^v" ),
+        ( "^v^", "^" ),
+        ( "^v^v^v^v^v", "^v" ),
+        ( ">^<>v<", ">^<>v<" ),
+        ( ">^<>v<>", ">^<>v" ),
+        ( "^<>v^", "^<>" ),
+        ( "^>>><<<v", "^>>><<<v" ),
+        ( "^>>><<<>>>", "^>>>" ),
+    ];
+
+    for (string, reference_result) in examples.into_iter() {
+        let directions: Vec<_> = string.chars().filter_map(parser).collect();
+        let result: String = canonicalize(&directions).iter()
+            .map(Step::glyph).collect();
+
+        assert_eq!(result, reference_result);
+    }
+}
+
+// a greedy tour that keeps heading for the nearest house it hasn't
+// been to yet, only ever walking through houses on the original route
+// falls back on the canonical route if the tour does no better
+fn compress<D: Step + Copy + PartialEq>(directions: &[D]) -> Vec<D> {
+    let canonical = canonicalize(directions);
+
+    let houses: HashSet<_> =
+        HousePath::from_directions(canonical.iter().cloned()).collect();
+
+    // walking the route forwards or backwards reaches every house
+    // so its moves and their opposites are all we need
+    let mut moves: Vec<D> = Vec::new();
+    for &direction in canonical.iter() {
+        for &m in [direction, direction.opposite()].iter() {
+            if !moves.contains(&m) {
+                moves.push(m);
+            }
+        }
+    }
+
+    let mut tour = Vec::new();
+    let mut remaining = houses.clone();
+    let mut location = (0, 0);
+    remaining.remove(&location);
+
+    while !remaining.is_empty() && tour.len() < canonical.len() {
+        // breadth first search for the nearest unvisited house
+        let mut came_from: HashMap<Location, (Location, D)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        let mut target = None;
+
+        queue.push_back(location);
+        while let Some(from) = queue.pop_front() {
+            if remaining.contains(&from) {
+                target = Some(from);
+                break;
+            }
+            for &direction in moves.iter() {
+                let to = direction.step(from);
+                if to != location && houses.contains(&to)
+                    && !came_from.contains_key(&to)
+                {
+                    came_from.insert(to, (from, direction));
+                    queue.push_back(to);
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut at = target.expect("houses on a route are all reachable");
+        while at != location {
+            let (from, direction) = came_from[&at];
+            remaining.remove(&at);
+            path.push(direction);
+            at = from;
+        }
+
+        tour.extend(path.into_iter().rev());
+        location = target.unwrap();
+    }
+
+    if remaining.is_empty() && tour.len() < canonical.len() {
+        tour
+    } else {
+        canonical
+    }
+}
+
+#[test]
+fn test_compress() {
+    let examples: Vec<(&str, usize)> = vec![
+        ( "", 0 ),
+        ( ">", 1 ),
+        ( "^v^v^v^v^v", 1 ),
+        ( "^>v<", 3 ),
+        ( "^>v<^>v<", 3 ),
+        ( ">>><<<>>>", 3 ),
+        ( ">>^^<<vv>>^^", 7 ),
+        ( "^>v>^>v>^", 9 ),
+    ];
+
+    for (string, reference_length) in examples.into_iter() {
+        let directions: Vec<_> = string.chars().filter_map(parser).collect();
+        let compressed = compress(&directions);
+
+        assert_eq!(compressed.len(), reference_length);
+
+        let houses: HashSet<_> =
+            HousePath::from_directions(directions).collect();
+        let compressed_houses: HashSet<_> =
+            HousePath::from_directions(compressed).collect();
+
+        assert_eq!(compressed_houses, houses);
+    }
+}
+
+#[test]
+fn test_compress_preserves_houses() {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut route = String::new();
+    File::open("data/day3.txt").unwrap().read_to_string(&mut route).unwrap();
+
+    let directions: Vec<_> = route.chars().filter_map(parser).collect();
+    let compressed = compress(&directions);
+
+    assert!(compressed.len() <= directions.len());
+
+    let houses: HashSet<_> =
+        HousePath::from_directions(directions).collect();
+    let compressed_houses: HashSet<_> =
+        HousePath::from_directions(compressed).collect();
+
+    assert_eq!(compressed_houses, houses);
+}
+
+struct Options {
+    walkers: usize,
+    hex: bool,
+    lenient: bool,
+    heatmap: bool,
+    loops: bool,
+    compress: bool,
+    render: Option<String>,
+}
+
+impl Options {
+    fn from_args<I: Iterator<Item=String>>(mut args: I)
+        -> Result<Options, String>
+    {
+        // santa works alone on a square grid unless told otherwise
+        let mut options = Options {
+            walkers: 1,
+            hex: false,
+            lenient: false,
+            heatmap: false,
+            loops: false,
+            compress: false,
+            render: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match &*arg {
+                "--walkers" => {
+                    options.walkers = args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or("--walkers needs a positive number")?;
+                }
+                "--hex" => { options.hex = true; }
+                "--lenient" => { options.lenient = true; }
+                "--heatmap" => { options.heatmap = true; }
+                "--loops" => { options.loops = true; }
+                "--compress" => { options.compress = true; }
+                "--render" => {
+                    options.render = Some(args.next()
+                        .filter(|f| ["ascii", "pgm", "svg"].contains(&&**f))
+                        .ok_or("--render needs a format: ascii, pgm or svg")?);
+                }
+                _ => { return Err(format!("unknown argument: {}", arg)); }
+            }
+        }
+
+        if options.compress && options.walkers != 1 {
+            return Err(String::from(
+                "--compress only works with a single walker"));
+        }
+
+        // loops are found along one walker's own path
+        if options.loops && options.walkers != 1 {
+            return Err(String::from("--loops only works with a single walker"));
+        }
+
+        Ok(options)
+    }
+}
+
+#[test]
+fn test_options() {
+    let args = |s: &'static str| s.split_whitespace().map(String::from);
+
+    let options = Options::from_args(args("--walkers 2 --render pgm"))
+        .unwrap();
+    assert_eq!(options.walkers, 2);
+    assert_eq!(options.render, Some(String::from("pgm")));
+
+    let bad_examples = [
+        "--walkers",
+        "--walkers 0",
+        "--render gif",
+        "--frobnicate",
+        "--loops --walkers 2",
+        "--compress --walkers 2",
+    ];
+
+    for &s in bad_examples.iter() {
+        assert!(Options::from_args(args(s)).is_err());
+    }
+}
+
+fn summarize_locations<I: Iterator>(locations: HousePath<I>, options: &Options)
+    -> String where
+    I::Item: Step,
+{
+    if options.render.as_deref() == Some("svg") {
+        return render_svg(locations.turns());
+    }
+
+    if options.heatmap || options.render.is_some() {
+        let counts = visit_counts(locations);
+
+        return match options.render.as_deref() {
+            Some("ascii") => render_ascii(&counts),
+            Some("pgm") => render_pgm(&counts),
+            _ => heatmap_report(&counts),
+        };
+    }
+
+    if options.loops {
+        return loop_report(locations);
+    }
+
+    let houses: VisitedSet = locations.collect();
+
+    format!("{:?}\n", houses.len())
+}
+
+fn summarize<D, I>(directions: I, options: &Options) -> String where
+    D: Step + Copy + PartialEq,
+    I: Iterator<Item=D>,
+{
+    if options.compress {
+        let directions: Vec<_> = directions.collect();
+        let mut route: String = compress(&directions).iter()
+            .map(Step::glyph).collect();
+
+        route.push('\n');
+        return route;
+    }
+
+    let house_path = HousePath::from_directions(directions)
+        .with_walkers(options.walkers);
+
+    summarize_locations(house_path, options)
+}
+
+fn summarize_route<D, F, I>(glyphs: I, parser: F, options: &Options)
+    -> Result<String, Vec<InvalidGlyph>> where
+    D: Step + Copy + PartialEq,
+    F: Fn(char) -> Option<D>,
+    I: Iterator<Item=(usize, char)>,
+{
+    if options.lenient {
+        let directions = glyphs.map(|(_, c)| c).filter_map(parser);
+
+        return Ok(summarize(directions, options));
+    }
+
+    let mut errors = Vec::new();
+    let summary = {
+        let directions = StrictDirections::new(glyphs, parser)
+            .filter_map(|result| match result {
+                Ok(direction) => Some(direction),
+                Err(e) => { errors.push(e); None },
+            });
+
+        summarize(directions, options)
+    };
+
+    if errors.is_empty() { Ok(summary) } else { Err(errors) }
+}
+
+// decodes utf-8 as it streams past, paired with byte offsets
+// so routes never need to be read up front
+// malformed sequences come out as U+FFFD, one per sequence
+struct Glyphs<R> {
+    reader: R,
+    offset: usize,
+}
+
+impl<R: BufRead> Glyphs<R> {
+    fn new(reader: R) -> Glyphs<R> {
+        Glyphs { reader, offset: 0 }
+    }
+
+    // the next byte is only looked at, it is left for consume to take
+    fn peek(&mut self) -> Option<io::Result<u8>> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buf) => { return buf.first().cloned().map(Ok); },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => { return Some(Err(e)); },
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Glyphs<R> {
+    type Item = io::Result<(usize, char)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = match self.peek()? {
+            Ok(b) => b,
+            Err(e) => { return Some(Err(e)); },
+        };
+        self.reader.consume(1);
+
+        let len = match first {
+            0x00..=0x7f => 1,
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => 0,
+        };
+
+        let mut sequence = [first, 0, 0, 0];
+        let mut n = 1;
+        while n < len {
+            match self.peek() {
+                Some(Ok(b)) if b & 0xc0 == 0x80 => {
+                    self.reader.consume(1);
+                    sequence[n] = b;
+                    n += 1;
+                }
+                Some(Err(e)) => { return Some(Err(e)); },
+                _ => { break; },
+            }
+        }
+
+        let offset = self.offset;
+        self.offset += n;
+
+        // overlong encodings and surrogates are caught here
+        let glyph = str::from_utf8(&sequence[..n]).ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or('\u{fffd}');
+
+        Some(Ok((offset, glyph)))
+    }
+}
+
+#[test]
+fn test_glyphs() {
+    type Glyph = (usize, char);
+
+    let examples: Vec<(&[u8], Vec<Glyph>)> = vec![
+        ( b"^>", vec![(0, '^'), (1, '>')] ),
+        ( "^\u{e9}v".as_bytes(), vec![(0, '^'), (1, '\u{e9}'), (3, 'v')] ),
+        ( "\u{1f385}<".as_bytes(), vec![(0, '\u{1f385}'), (4, '<')] ),
+        ( b"\xff^", vec![(0, '\u{fffd}'), (1, '^')] ),
+        ( b"\xc3^", vec![(0, '\u{fffd}'), (1, '^')] ),
+        ( b"\xe2\x82", vec![(0, '\u{fffd}')] ),
+        ( b"\xc0\xbe", vec![(0, '\u{fffd}'), (1, '\u{fffd}')] ),
+        ( b"\xed\xa0\x80", vec![(0, '\u{fffd}')] ),
+    ];
+
+    for (bytes, reference) in examples.into_iter() {
+        let glyphs: Result<Vec<_>, _> = Glyphs::new(bytes).collect();
+        assert_eq!(glyphs.unwrap(), reference);
+
+        // sequences split across reads decode the same
+        let reader = io::BufReader::with_capacity(1, bytes);
+        let glyphs: Result<Vec<_>, _> = Glyphs::new(reader).collect();
+        assert_eq!(glyphs.unwrap(), reference);
+    }
+
+    let errors: Vec<_> = StrictDirections::new(
+        Glyphs::new("^\u{e9}v".as_bytes()).map(Result::unwrap), parser)
+        .filter_map(Result::err).collect();
+    assert_eq!(errors, vec![InvalidGlyph { offset: 1, glyph: '\u{e9}' }]);
+}
+
+fn main() {
+    let stdin = io::stdin();
+
+    let options = match Options::from_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // routes are streamed rather than read up front
+    // the first read error ends the route, and is reported once it has been
+    let mut read_error = None;
+    let summary = {
+        let glyphs = Glyphs::new(stdin.lock())
+            .scan(&mut read_error, |read_error, glyph| match glyph {
+                Ok(glyph) => Some(glyph),
+                Err(e) => { **read_error = Some(e); None }
+            });
+
+        if options.hex {
+            summarize_route(glyphs, hex_parser, &options)
+        } else {
+            summarize_route(glyphs, parser, &options)
+        }
+    };
+
+    if let Some(e) = read_error {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    match summary {
+        Ok(summary) => print!("{}", summary),
+        Err(errors) => {
+            for e in errors {
+                eprintln!("invalid glyph {:?} at offset {}", e.glyph, e.offset);
+            }
+            process::exit(1);
+        }
+    }
 }
 
 #[test]
@@ -156,3 +1507,22 @@ fn test() {
         assert_eq!(count, reference_count);
     }
 }
+
+#[test]
+fn test_with_robo_santa() {
+    let examples: Vec<(&str, usize)> = vec![
+        ( "^v", 3 ),
+        ( "^>v<", 3 ),
+        ( "^v^v^v^v^v", 11 ),
+    ];
+
+    for (string, reference_count) in examples.into_iter() {
+        let directions = string.chars().filter_map(parser);
+        let house_path = HousePath::from_directions(directions)
+            .with_walkers(2);
+        let houses: HashSet<_> = house_path.collect();
+        let count = houses.len();
+
+        assert_eq!(count, reference_count);
+    }
+}
@@ -2,13 +2,286 @@ extern crate md5;
 extern crate rustc_serialize;
 
 use rustc_serialize::hex::ToHex;
-use std::{iter, i32};
+use std::env;
 use std::io::{self, Read};
+use std::ops::Range;
+use std::process;
 
-fn hash(key: &str, number: i32) -> md5::Digest {
+trait HashFunction {
+    fn compute(&self, data: &[u8]) -> Vec<u8>;
+}
+
+struct Md5;
+
+impl HashFunction for Md5 {
+    fn compute(&self, data: &[u8]) -> Vec<u8> {
+        md5::compute(data).to_vec()
+    }
+}
+
+struct Sha1;
+
+impl HashFunction for Sha1 {
+    fn compute(&self, data: &[u8]) -> Vec<u8> {
+        sha::sha1(data).to_vec()
+    }
+}
+
+struct Sha256;
+
+impl HashFunction for Sha256 {
+    fn compute(&self, data: &[u8]) -> Vec<u8> {
+        sha::sha256(data).to_vec()
+    }
+}
+
+// not a real hash -- just the byte sum, so results are easy to work out
+#[cfg(test)]
+struct Checksum;
+
+#[cfg(test)]
+impl HashFunction for Checksum {
+    fn compute(&self, data: &[u8]) -> Vec<u8> {
+        let sum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+
+        vec![sum]
+    }
+}
+
+mod sha {
+    // both algorithms share the same padding and block layout
+
+    fn pad(data: &[u8]) -> Vec<u8> {
+        let bits = (data.len() as u64).wrapping_mul(8);
+        let mut padded = data.to_vec();
+
+        padded.push(0x80);
+        while padded.len() % 64 != 56 {
+            padded.push(0);
+        }
+        for i in (0..8).rev() {
+            padded.push((bits >> (8 * i)) as u8);
+        }
+
+        padded
+    }
+
+    fn word(bytes: &[u8]) -> u32 {
+        bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u32)
+    }
+
+    fn write_words(words: &[u32], out: &mut [u8]) {
+        for (i, w) in words.iter().enumerate() {
+            for j in 0..4 {
+                out[4 * i + j] = (w >> (24 - 8 * j)) as u8;
+            }
+        }
+    }
+
+    pub fn sha1(data: &[u8]) -> [u8; 20] {
+        let mut h: [u32; 5] = [
+            0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0,
+        ];
+
+        for block in pad(data).chunks(64) {
+            let mut w = [0u32; 80];
+            for i in 0..16 {
+                w[i] = word(&block[4 * i..4 * i + 4]);
+            }
+            for i in 16..80 {
+                w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16])
+                    .rotate_left(1);
+            }
+
+            let (mut a, mut b, mut c, mut d, mut e) =
+                (h[0], h[1], h[2], h[3], h[4]);
+
+            for (i, &wi) in w.iter().enumerate() {
+                let (f, k) =
+                    if i < 20 { ((b & c) | (!b & d), 0x5a827999) }
+                    else if i < 40 { (b ^ c ^ d, 0x6ed9eba1) }
+                    else if i < 60 { ((b & c) | (b & d) | (c & d), 0x8f1bbcdc) }
+                    else { (b ^ c ^ d, 0xca62c1d6) };
+
+                let temp = a.rotate_left(5)
+                    .wrapping_add(f)
+                    .wrapping_add(e)
+                    .wrapping_add(k)
+                    .wrapping_add(wi);
+
+                e = d;
+                d = c;
+                c = b.rotate_left(30);
+                b = a;
+                a = temp;
+            }
+
+            for (x, y) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+                *x = x.wrapping_add(*y);
+            }
+        }
+
+        let mut digest = [0u8; 20];
+        write_words(&h, &mut digest);
+        digest
+    }
+
+    const K256: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5,
+        0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+        0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc,
+        0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+        0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3,
+        0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5,
+        0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+        0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+
+    pub fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut h: [u32; 8] = [
+            0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+            0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+        ];
+
+        for block in pad(data).chunks(64) {
+            let mut w = [0u32; 64];
+            for i in 0..16 {
+                w[i] = word(&block[4 * i..4 * i + 4]);
+            }
+            for i in 16..64 {
+                let s0 = w[i - 15].rotate_right(7)
+                    ^ w[i - 15].rotate_right(18)
+                    ^ (w[i - 15] >> 3);
+                let s1 = w[i - 2].rotate_right(17)
+                    ^ w[i - 2].rotate_right(19)
+                    ^ (w[i - 2] >> 10);
+                w[i] = w[i - 16]
+                    .wrapping_add(s0)
+                    .wrapping_add(w[i - 7])
+                    .wrapping_add(s1);
+            }
+
+            let mut v = h;
+
+            for i in 0..64 {
+                let s1 = v[4].rotate_right(6)
+                    ^ v[4].rotate_right(11)
+                    ^ v[4].rotate_right(25);
+                let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+                let temp1 = v[7]
+                    .wrapping_add(s1)
+                    .wrapping_add(ch)
+                    .wrapping_add(K256[i])
+                    .wrapping_add(w[i]);
+                let s0 = v[0].rotate_right(2)
+                    ^ v[0].rotate_right(13)
+                    ^ v[0].rotate_right(22);
+                let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+                let temp2 = s0.wrapping_add(maj);
+
+                v = [
+                    temp1.wrapping_add(temp2), v[0], v[1], v[2],
+                    v[3].wrapping_add(temp1), v[4], v[5], v[6],
+                ];
+            }
+
+            for (x, y) in h.iter_mut().zip(v.iter()) {
+                *x = x.wrapping_add(*y);
+            }
+        }
+
+        let mut digest = [0u8; 32];
+        write_words(&h, &mut digest);
+        digest
+    }
+
+    #[test]
+    fn test() {
+        use rustc_serialize::hex::ToHex;
+
+        let long = "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let examples: Vec<(&str, &str, &str)> = vec![
+            (
+                "",
+                "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                "abc",
+                "a9993e364706816aba3e25717850c26c9cd0d89d",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                long,
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+
+        for (data, reference_sha1, reference_sha256) in examples.into_iter() {
+            assert_eq!(sha1(data.as_bytes()).to_hex(), reference_sha1);
+            assert_eq!(sha256(data.as_bytes()).to_hex(), reference_sha256);
+        }
+    }
+}
+
+trait Prefix {
+    fn matches(&self, digest: &[u8]) -> bool;
+}
+
+// leading zeroes in the hex representation of the digest
+struct ZeroNibbles(usize);
+
+impl Prefix for ZeroNibbles {
+    fn matches(&self, digest: &[u8]) -> bool {
+        let zeroes = self.0;
+
+        if digest.len() * 2 < zeroes {
+            return false;
+        }
+        if !digest[..zeroes / 2].iter().all(|&b| b == 0) {
+            return false;
+        }
+
+        zeroes.is_multiple_of(2) || digest[zeroes / 2] >> 4 == 0
+    }
+}
+
+impl<F: Fn(&[u8]) -> bool> Prefix for F {
+    fn matches(&self, digest: &[u8]) -> bool {
+        self(digest)
+    }
+}
+
+#[test]
+fn test_zero_nibbles() {
+    let examples: Vec<(usize, Vec<u8>, bool)> = vec![
+        ( 0, vec![0xff], true ),
+        ( 1, vec![0x0f], true ),
+        ( 1, vec![0x10], false ),
+        ( 2, vec![0x00, 0xff], true ),
+        ( 3, vec![0x00, 0x0f], true ),
+        ( 3, vec![0x00, 0x10], false ),
+        ( 3, vec![0x00], false ),
+    ];
+
+    for (zeroes, digest, reference_result) in examples.into_iter() {
+        assert_eq!(ZeroNibbles(zeroes).matches(&digest), reference_result);
+    }
+}
+
+fn hash<H: HashFunction>(hasher: &H, key: &str, number: i32) -> Vec<u8> {
     let data = format!("{}{}", key, number).into_bytes();
 
-    md5::compute(&data)
+    hasher.compute(&data)
 }
 
 #[test]
@@ -19,24 +292,115 @@ fn test_hash() {
     ];
 
     for (key, number, reference_result) in examples.into_iter() {
-        let result = hash(key, number).to_hex();
+        let result = hash(&Md5, key, number).to_hex();
 
         assert!(result.starts_with(reference_result));
     }
 }
 
-#[inline]
-fn find_sufficient_number(key: &str, zeroes: usize) -> Result<i32, String> {
-    let prefix: String = iter::repeat('0').take(zeroes).collect();
-    let max: i32 = i32::MAX;
+struct SufficientNumbers<'a, H, P> {
+    hasher: H,
+    key: &'a str,
+    prefix: P,
+    numbers: Range<i32>,
+}
 
-    for i in 0..max {
-        if hash(key, i).to_hex().starts_with(&prefix) {
-            return Ok(i);
+impl<'a, H: HashFunction, P: Prefix> SufficientNumbers<'a, H, P> {
+    fn new(hasher: H, key: &'a str, prefix: P, numbers: Range<i32>) -> Self {
+        SufficientNumbers {
+            hasher,
+            key,
+            prefix,
+            numbers,
         }
     }
+}
 
-    Err(format!("no sufficient number found in range: 0..{}", max))
+impl<'a, H: HashFunction, P: Prefix> Iterator for SufficientNumbers<'a, H, P> {
+    type Item = (i32, String);
+
+    fn next(&mut self) -> Option<(i32, String)> {
+        // keep hashing until a match turns up or the range runs dry
+        for i in self.numbers.by_ref() {
+            let digest = hash(&self.hasher, self.key, i);
+
+            if self.prefix.matches(&digest) {
+                return Some((i, digest.to_hex()));
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_sufficient_numbers() {
+    let examples: Vec<(&str, usize, Range<i32>, Vec<i32>)> = vec![
+        ( "abcdef", 5, 0..609044, vec![609043] ),
+        ( "abcdef", 5, 0..609043, vec![] ),
+        ( "pqrstuv", 5, 1048970..1048971, vec![1048970] ),
+    ];
+
+    for (key, zeroes, numbers, reference_numbers) in examples.into_iter() {
+        let prefix = ZeroNibbles(zeroes);
+        let found: Vec<_> = SufficientNumbers::new(Md5, key, prefix, numbers)
+            .collect();
+
+        for &(number, ref digest) in found.iter() {
+            assert!(digest.starts_with("00000"));
+            assert_eq!(*digest, hash(&Md5, key, number).to_hex());
+        }
+
+        let numbers: Vec<_> = found.into_iter().map(|(i, _)| i).collect();
+        assert_eq!(numbers, reference_numbers);
+    }
+}
+
+#[test]
+fn test_sufficient_numbers_frequency() {
+    // each leading zero should be a 1 in 16 event
+    // so about 256 matches are expected here
+    let prefix = ZeroNibbles(2);
+    let max = 16 * 16 * 256;
+
+    let count = SufficientNumbers::new(Md5, "abcdef", prefix, 0..max).count();
+
+    assert!(count > 256 / 2 && count < 256 * 2);
+}
+
+#[test]
+fn test_sufficient_numbers_with_other_hashes() {
+    let first = |numbers: Vec<(i32, String)>| numbers[0].0;
+
+    let sha1 = SufficientNumbers::new(
+        Sha1, "abcdef", ZeroNibbles(4), 0..200000);
+    assert_eq!(first(sha1.take(1).collect()), 185343);
+
+    let sha256 = SufficientNumbers::new(
+        Sha256, "abcdef", ZeroNibbles(4), 0..100000);
+    assert_eq!(first(sha256.take(1).collect()), 71479);
+
+    // "abc1799" has a byte sum of 0x200
+    let checksum = SufficientNumbers::new(
+        Checksum, "abc", ZeroNibbles(2), 0..2000);
+    assert_eq!(first(checksum.collect()), 1799);
+
+    let odd = |digest: &[u8]| digest[0] % 2 == 1;
+    let checksum = SufficientNumbers::new(Checksum, "", odd, 0..10);
+    let numbers: Vec<_> = checksum.map(|(i, _)| i).collect();
+    assert_eq!(numbers, vec![1, 3, 5, 7, 9]);
+}
+
+fn find_sufficient_number<H: HashFunction>(hasher: H, key: &str, zeroes: usize)
+    -> Result<i32, String>
+{
+    let max: i32 = i32::MAX;
+    let prefix = ZeroNibbles(zeroes);
+
+    match SufficientNumbers::new(hasher, key, prefix, 0..max).next() {
+        Some((i, _)) => Ok(i),
+        None => Err(format!("no sufficient number found in range: 0..{}", max)),
+    }
 }
 
 #[test]
@@ -47,7 +411,7 @@ fn test() {
     ];
 
     for (key, zeroes, reference_number) in examples.into_iter() {
-        let number = find_sufficient_number(key, zeroes);
+        let number = find_sufficient_number(Md5, key, zeroes);
 
         assert_eq!(number, Ok(reference_number));
     }
@@ -61,5 +425,21 @@ fn main() {
 
     let key: String = buf.chars().filter(|c| c.is_alphabetic()).collect();
 
-    println!("{}", find_sufficient_number(&key, 5).unwrap());
+    // pick the hash function by name, md5 unless told otherwise
+    let algorithm = env::args().nth(1).unwrap_or(String::from("md5"));
+
+    let number = match &*algorithm {
+        "md5" => find_sufficient_number(Md5, &key, 5),
+        "sha1" => find_sufficient_number(Sha1, &key, 5),
+        "sha256" => find_sufficient_number(Sha256, &key, 5),
+        _ => Err(format!("unknown hash function: {}", algorithm)),
+    };
+
+    match number {
+        Ok(number) => println!("{}", number),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 }
@@ -1,5 +1,17 @@
-use std::io::{self, BufRead};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::process;
+use std::str;
 
+use rules::Mode;
+
+// the hand-written predicates below are the reference the rule engine
+// is checked against, the binary itself goes through the rules
+
+#[cfg(test)]
 fn string_contains_three_vowels(string: &str) -> bool {
     let vowels = "aeiou";
     let count = string.chars()
@@ -24,6 +36,7 @@ fn test_string_contains_three_vowels() {
     }
 }
 
+#[cfg(test)]
 fn string_contains_double_letter(string: &str) -> bool {
     let iter1 = string.chars();
     let mut iter2 = iter1.clone();
@@ -50,6 +63,7 @@ fn test_string_contains_double_letter() {
     }
 }
 
+#[cfg(test)]
 fn string_contains_blacklisted_substring(string: &str) -> bool {
     let blacklisted_substrings = ["ab", "cd", "pq", "xy"];
 
@@ -74,6 +88,7 @@ fn test_string_contains_blacklisted_substring() {
     }
 }
 
+#[cfg(test)]
 fn string_is_nice(string: &str) -> bool {
     string_contains_three_vowels(string)
     && string_contains_double_letter(string)
@@ -97,14 +112,1329 @@ fn test_string_is_nice() {
     }
 }
 
+// bit n is set for the nth lowercase letter, counting from 'a' = 1
+const VOWEL_BITS: u32 = 0x208222;
+// blacklisted pairs are always a letter followed by the next one
+// so it's enough to know which letters start one: a, c, p and x
+const BLACKLIST_BITS: u32 = 0x101000a;
+
+fn letter_in(bits: u32, b: u8) -> bool {
+    b.is_ascii_lowercase() & ((bits >> (b & 31)) & 1 == 1)
+}
+
+// the original rules in a single pass over the bytes
+// multi-byte characters never repeat byte for byte the way they do
+// letter for letter, so this only agrees with the rules on ascii
+fn bytes_are_nice(bytes: &[u8]) -> bool {
+    let mut vowels = 0;
+    let mut double_letter = false;
+    let mut blacklisted = false;
+    // nothing comes before the first byte, not even a nul
+    let mut previous: Option<u8> = None;
+
+    // no early exits, so there's nothing for the branch predictor to miss
+    for &b in bytes {
+        vowels += letter_in(VOWEL_BITS, b) as usize;
+        double_letter |= previous == Some(b);
+        blacklisted |= previous.is_some_and(|p|
+            (b == p.wrapping_add(1)) & letter_in(BLACKLIST_BITS, p));
+        previous = Some(b);
+    }
+
+    vowels >= 3 && double_letter && !blacklisted
+}
+
+#[test]
+fn test_bytes_are_nice() {
+    // every string up to six bytes long over an alphabet that
+    // covers the vowels, a double letter and each blacklisted pair
+    // along with bytes either side of the letters, and nul
+    let alphabet = b"abcdepqxy\0`{";
+    let mut strings = vec![String::new()];
+
+    for _ in 0..6 {
+        let longer: Vec<String> = strings.iter()
+            .filter(|s| s.len() == strings.last().unwrap().len())
+            .flat_map(|s| alphabet.iter().map(move |&c| {
+                let mut longer = s.clone();
+                longer.push(c as char);
+                longer
+            }))
+            .collect();
+
+        strings.extend(longer);
+    }
+
+    for string in strings.iter() {
+        assert_eq!(bytes_are_nice(string.as_bytes()), string_is_nice(string),
+                   "disagreement on {:?}", string);
+    }
+}
+
+#[test]
+#[ignore]
+fn bench_bytes_are_nice() {
+    // cargo test --bin day5 -- --ignored --nocapture
+    use std::fs::File;
+    use std::time::Instant;
+
+    let f = File::open("data/day5.txt").unwrap();
+    let lines: Vec<String> = io::BufReader::new(f).lines()
+        .map(Result::unwrap).collect();
+    let rounds = 1000;
+
+    let start = Instant::now();
+    let mut expected = 0;
+    for _ in 0..rounds {
+        expected += lines.iter().filter(|s| string_is_nice(s)).count();
+    }
+    let predicates = start.elapsed();
+
+    let start = Instant::now();
+    let mut count = 0;
+    for _ in 0..rounds {
+        count += lines.iter().filter(|s| bytes_are_nice(s.as_bytes())).count();
+    }
+    let single_pass = start.elapsed();
+
+    assert_eq!(count, expected);
+    println!("string_is_nice: {:?}", predicates);
+    println!("bytes_are_nice: {:?}", single_pass);
+}
+
+// indices of the first pair of letters seen twice without overlapping
+// letters come with their offsets, which take no part in the comparison
+fn repeated_pair<T: Eq + Hash>(letters: &[(usize, T)])
+    -> Option<(usize, usize)>
+{
+    let mut first_seen = HashMap::new();
+
+    // a pair only counts again once it has stopped overlapping itself
+    for (i, pair) in letters.windows(2).enumerate() {
+        let first = *first_seen.entry((&pair[0].1, &pair[1].1)).or_insert(i);
+
+        if i >= first + 2 {
+            return Some((first, i));
+        }
+    }
+
+    None
+}
+
+// offsets of the first pair of letters seen twice without overlapping
+#[cfg(test)]
+fn find_repeated_pair(string: &str) -> Option<(usize, usize)> {
+    let chars: Vec<(usize, char)> = string.char_indices().collect();
+
+    repeated_pair(&chars).map(|(i, j)| (chars[i].0, chars[j].0))
+}
+
+#[cfg(test)]
+fn string_contains_repeated_pair(string: &str) -> bool {
+    find_repeated_pair(string).is_some()
+}
+
+#[test]
+fn test_string_contains_repeated_pair() {
+    let examples: Vec<(&str, bool)> = vec![
+        ("xyxy", true),
+        ("aabcdefgaa", true),
+        ("aaa", false),
+        ("aaaa", true),
+        ("qjhvhtzxzqqjkmpb", true),
+        ("ieodomkazucvgmuy", false),
+    ];
+
+    for (string, reference_result) in examples.into_iter() {
+        let result = string_contains_repeated_pair(string);
+
+        assert_eq!(result, reference_result);
+    }
+
+    assert_eq!(find_repeated_pair("aabcdefgaa"), Some((0, 8)));
+    assert_eq!(find_repeated_pair("aaaa"), Some((0, 2)));
+    assert_eq!(find_repeated_pair("aaa"), None);
+}
+
+#[cfg(test)]
+fn string_contains_sandwiched_letter(string: &str) -> bool {
+    let chars: Vec<char> = string.chars().collect();
+
+    chars.windows(3).any(|w| w[0] == w[2])
+}
+
+#[test]
+fn test_string_contains_sandwiched_letter() {
+    let examples: Vec<(&str, bool)> = vec![
+        ("xyx", true),
+        ("abcdefeghi", true),
+        ("aaa", true),
+        ("uurcxstgmygtbstg", false),
+        ("ieodomkazucvgmuy", true),
+    ];
+
+    for (string, reference_result) in examples.into_iter() {
+        let result = string_contains_sandwiched_letter(string);
+
+        assert_eq!(result, reference_result);
+    }
+}
+
+#[cfg(test)]
+fn string_is_nice_revised(string: &str) -> bool {
+    string_contains_repeated_pair(string)
+    && string_contains_sandwiched_letter(string)
+}
+
+#[test]
+fn test_string_is_nice_revised() {
+    let examples: Vec<(&str, bool)> = vec![
+        ("qjhvhtzxzqqjkmpb", true),
+        ("xxyxx", true),
+        ("aaa", false),
+        ("uurcxstgmygtbstg", false),
+        ("ieodomkazucvgmuy", false),
+    ];
+
+    for (string, should_be_nice) in examples.into_iter() {
+        let is_nice = string_is_nice_revised(string);
+
+        assert_eq!(is_nice, should_be_nice);
+    }
+}
+
+mod rules {
+    use std::borrow::Cow;
+    use std::fmt;
+    use std::str::{self, FromStr};
+
+    // rule sets are written as s-expressions, for example
+    //
+    //   ; comments run to the end of the line
+    //   (and (at-least 3 "aeiou")
+    //        (repeat-letter 0)
+    //        (not (contains "ab" "cd" "pq" "xy")))
+    //
+    // (at-least N "chars")  at least N letters drawn from "chars"
+    // (contains "s" ...)    any of the given substrings
+    // (repeat-letter N)     a letter recurring with N letters in between
+    // (repeat-pair)         a pair of letters twice without overlapping
+    // (and ...) (or ...) (not rule)
+    //
+    // rules look at letters, which are whatever the mode cuts strings into
+    // substrings and letter classes in a rule are cut up the same way
+
+    #[derive(Debug, PartialEq)]
+    pub enum Rule {
+        AtLeast(usize, String),
+        Contains(Vec<String>),
+        RepeatLetter(usize),
+        RepeatPair,
+        And(Vec<Rule>),
+        Or(Vec<Rule>),
+        Not(Box<Rule>),
+    }
+
+    pub const ORIGINAL: &str = r#"
+        (and (at-least 3 "aeiou")
+             (repeat-letter 0)
+             (not (contains "ab" "cd" "pq" "xy")))
+    "#;
+
+    pub const REVISED: &str = r#"
+        (and (repeat-pair)
+             (repeat-letter 1))
+    "#;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Mode {
+        // utf-8 text, one letter per character
+        Text,
+        // any bytes at all, one letter per byte
+        Bytes,
+        // utf-8 text, case folded, one letter per grapheme
+        Unicode,
+    }
+
+    // the bytes of a letter, and the offset in the string they start at
+    type Letter<'a> = (usize, Cow<'a, [u8]>);
+
+    fn split_bytes<'a>(bytes: &'a [u8]) -> Vec<Letter<'a>> {
+        (0..bytes.len()).map(|i| (i, Cow::Borrowed(&bytes[i..i + 1]))).collect()
+    }
+
+    impl Mode {
+        fn split<'a>(self, string: &'a str) -> Vec<Letter<'a>> {
+            match self {
+                Mode::Text => string.char_indices()
+                    .map(|(i, c)| {
+                        let letter = &string[i..i + c.len_utf8()];
+                        (i, Cow::Borrowed(letter.as_bytes()))
+                    })
+                    .collect(),
+                Mode::Bytes => split_bytes(string.as_bytes()),
+                Mode::Unicode => ::unicode::graphemes(string).into_iter()
+                    .map(|(i, g)| (i, Cow::Owned(g.into_bytes())))
+                    .collect(),
+            }
+        }
+    }
+
+    // a letter with accents on it still belongs to the unaccented class
+    fn in_class(letter: &[u8], class: &[Letter]) -> bool {
+        class.iter().any(|(_, c)| letter.starts_with(c))
+    }
+
+    // index of the letter where the needle first turns up
+    fn find(letters: &[Letter], needle: &[Letter]) -> Option<usize> {
+        if needle.is_empty() {
+            return Some(0);
+        }
+
+        letters.windows(needle.len())
+            .position(|w| w.iter().zip(needle).all(|(a, b)| a.1 == b.1))
+    }
+
+    // indices of the first letter recurring with gap letters in between
+    fn repeat_letter(letters: &[Letter], gap: usize) -> Option<(usize, usize)> {
+        // a gap too big to count can't fit in any string
+        let width = gap.checked_add(2)?;
+
+        letters.windows(width)
+            .position(|w| w[0].1 == w[width - 1].1)
+            .map(|i| (i, i + width - 1))
+    }
+
+    // letters that aren't utf-8 on their own are shown byte by byte
+    fn show(letter: &[u8]) -> String {
+        match str::from_utf8(letter) {
+            Ok(s) => s.to_string(),
+            Err(_) => letter.iter().map(|b| format!("\\x{:02x}", b)).collect(),
+        }
+    }
+
+    impl Rule {
+        pub fn matches(&self, string: &str) -> bool {
+            self.matches_in(Mode::Text, string)
+        }
+
+        pub fn matches_in(&self, mode: Mode, string: &str) -> bool {
+            self.matches_letters(mode, &mode.split(string))
+        }
+
+        pub fn matches_bytes(&self, bytes: &[u8]) -> bool {
+            self.matches_letters(Mode::Bytes, &split_bytes(bytes))
+        }
+
+        fn matches_letters(&self, mode: Mode, letters: &[Letter]) -> bool {
+            match *self {
+                Rule::AtLeast(n, ref class) => {
+                    let class = mode.split(class);
+                    letters.iter()
+                        .filter(|l| in_class(&l.1, &class))
+                        .count() >= n
+                }
+                Rule::Contains(ref substrings) => substrings.iter()
+                    .any(|s| find(letters, &mode.split(s)).is_some()),
+                Rule::RepeatLetter(gap) =>
+                    repeat_letter(letters, gap).is_some(),
+                Rule::RepeatPair => ::repeated_pair(letters).is_some(),
+                Rule::And(ref rules) =>
+                    rules.iter().all(|r| r.matches_letters(mode, letters)),
+                Rule::Or(ref rules) =>
+                    rules.iter().any(|r| r.matches_letters(mode, letters)),
+                Rule::Not(ref rule) => !rule.matches_letters(mode, letters),
+            }
+        }
+    }
+
+    // what a rule turned up while checking a string
+    // offsets are in bytes from the start of the string
+    #[derive(Debug, PartialEq)]
+    pub enum Evidence {
+        Nothing,
+        Letters(Vec<(usize, String)>),
+        Substring(usize, String),
+        Repeat(String, usize, usize),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Explanation {
+        pub rule: String,
+        pub passed: bool,
+        pub evidence: Evidence,
+        pub reasons: Vec<Explanation>,
+    }
+
+    impl Rule {
+        pub fn explain_in(&self, mode: Mode, string: &str) -> Explanation {
+            self.explain_letters(mode, &mode.split(string))
+        }
+
+        pub fn explain_bytes(&self, bytes: &[u8]) -> Explanation {
+            self.explain_letters(Mode::Bytes, &split_bytes(bytes))
+        }
+
+        fn explain_letters(&self, mode: Mode, letters: &[Letter])
+            -> Explanation
+        {
+            let (passed, evidence, reasons) = match *self {
+                Rule::AtLeast(n, ref class) => {
+                    let class = mode.split(class);
+                    let letters: Vec<_> = letters.iter()
+                        .filter(|l| in_class(&l.1, &class))
+                        .map(|&(i, ref l)| (i, show(l)))
+                        .collect();
+
+                    (letters.len() >= n, Evidence::Letters(letters), vec![])
+                }
+                Rule::Contains(ref substrings) => {
+                    // whichever substring turns up first
+                    let found = substrings.iter()
+                        .filter_map(|s| find(letters, &mode.split(s))
+                            .map(|i| (letters.get(i).map_or(0, |l| l.0), s)))
+                        .min();
+
+                    match found {
+                        Some((i, s)) =>
+                            (true, Evidence::Substring(i, s.clone()), vec![]),
+                        None => (false, Evidence::Nothing, vec![]),
+                    }
+                }
+                Rule::RepeatLetter(gap) => match repeat_letter(letters, gap) {
+                    Some((i, j)) => {
+                        let (a, b) = (&letters[i], &letters[j]);
+                        (true, Evidence::Repeat(show(&a.1), a.0, b.0), vec![])
+                    }
+                    None => (false, Evidence::Nothing, vec![]),
+                },
+                Rule::RepeatPair => match ::repeated_pair(letters) {
+                    Some((i, j)) => {
+                        let pair =
+                            show(&letters[i].1) + &show(&letters[i + 1].1);
+                        let (i, j) = (letters[i].0, letters[j].0);
+                        (true, Evidence::Repeat(pair, i, j), vec![])
+                    }
+                    None => (false, Evidence::Nothing, vec![]),
+                },
+                Rule::And(ref rules) => {
+                    let reasons: Vec<_> = rules.iter()
+                        .map(|r| r.explain_letters(mode, letters)).collect();
+                    let passed = reasons.iter().all(|r| r.passed);
+
+                    (passed, Evidence::Nothing, reasons)
+                }
+                Rule::Or(ref rules) => {
+                    let reasons: Vec<_> = rules.iter()
+                        .map(|r| r.explain_letters(mode, letters)).collect();
+                    let passed = reasons.iter().any(|r| r.passed);
+
+                    (passed, Evidence::Nothing, reasons)
+                }
+                Rule::Not(ref rule) => {
+                    let reason = rule.explain_letters(mode, letters);
+
+                    (!reason.passed, Evidence::Nothing, vec![reason])
+                }
+            };
+
+            // combinators just name themselves, their reasons say the rest
+            let rule = match *self {
+                Rule::And(_) => String::from("(and ...)"),
+                Rule::Or(_) => String::from("(or ...)"),
+                Rule::Not(_) => String::from("(not ...)"),
+                _ => self.to_string(),
+            };
+
+            Explanation { rule, passed, evidence, reasons }
+        }
+    }
+
+    impl fmt::Display for Rule {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let list = |f: &mut fmt::Formatter, name, rules: &[Rule]| {
+                write!(f, "({}", name)?;
+                for rule in rules {
+                    write!(f, " {}", rule)?;
+                }
+                write!(f, ")")
+            };
+
+            match *self {
+                Rule::AtLeast(n, ref class) =>
+                    write!(f, "(at-least {} \"{}\")", n, class),
+                Rule::Contains(ref substrings) => {
+                    write!(f, "(contains")?;
+                    for s in substrings {
+                        write!(f, " \"{}\"", s)?;
+                    }
+                    write!(f, ")")
+                }
+                Rule::RepeatLetter(gap) => write!(f, "(repeat-letter {})", gap),
+                Rule::RepeatPair => write!(f, "(repeat-pair)"),
+                Rule::And(ref rules) => list(f, "and", rules),
+                Rule::Or(ref rules) => list(f, "or", rules),
+                Rule::Not(ref rule) => write!(f, "(not {})", rule),
+            }
+        }
+    }
+
+    impl fmt::Display for Evidence {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                Evidence::Nothing => write!(f, "nothing"),
+                Evidence::Letters(ref letters) if letters.is_empty() =>
+                    write!(f, "nothing"),
+                Evidence::Letters(ref letters) => {
+                    let found: Vec<_> = letters.iter()
+                        .map(|&(i, ref c)| format!("{} at {}", c, i))
+                        .collect();
+                    write!(f, "{}", found.join(", "))
+                }
+                Evidence::Substring(i, ref s) => write!(f, "{} at {}", s, i),
+                Evidence::Repeat(ref s, i, j) =>
+                    write!(f, "{} at {} and {}", s, i, j),
+            }
+        }
+    }
+
+    impl Explanation {
+        fn write_indented(&self, f: &mut fmt::Formatter, depth: usize)
+            -> fmt::Result
+        {
+            let verdict = if self.passed { "pass" } else { "fail" };
+
+            write!(f, "{:indent$}{} {}", "", verdict, self.rule,
+                   indent = depth * 2)?;
+            if self.reasons.is_empty() {
+                write!(f, ": found {}", self.evidence)?;
+            }
+            writeln!(f)?;
+
+            for reason in self.reasons.iter() {
+                reason.write_indented(f, depth + 1)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for Explanation {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.write_indented(f, 0)
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Token {
+        Open,
+        Close,
+        Word(String),
+        Quoted(String),
+    }
+
+    fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => tokens.push(Token::Open),
+                ')' => tokens.push(Token::Close),
+                ';' => {
+                    while chars.peek().is_some_and(|&c| c != '\n') {
+                        chars.next();
+                    }
+                }
+                '"' => {
+                    let mut quoted = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => quoted.push(c),
+                            None => {
+                                return Err(String::from("unterminated string"));
+                            }
+                        }
+                    }
+                    tokens.push(Token::Quoted(quoted));
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut word = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || "()\";".contains(c) {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    fn number(tokens: &[Token], i: &mut usize) -> Result<usize, String> {
+        match tokens.get(*i) {
+            Some(Token::Word(word)) => {
+                *i += 1;
+                word.parse()
+                    .map_err(|_| format!("expected a number, found {}", word))
+            }
+            other => Err(format!("expected a number, found {:?}", other)),
+        }
+    }
+
+    fn quoted(tokens: &[Token], i: &mut usize) -> Result<String, String> {
+        match tokens.get(*i) {
+            Some(Token::Quoted(quoted)) => {
+                *i += 1;
+                Ok(quoted.clone())
+            }
+            other => Err(format!("expected a string, found {:?}", other)),
+        }
+    }
+
+    fn rules(tokens: &[Token], i: &mut usize) -> Result<Vec<Rule>, String> {
+        let mut rules = Vec::new();
+
+        while tokens.get(*i) == Some(&Token::Open) {
+            rules.push(rule(tokens, i)?);
+        }
+
+        Ok(rules)
+    }
+
+    fn rule(tokens: &[Token], i: &mut usize) -> Result<Rule, String> {
+        if tokens.get(*i) != Some(&Token::Open) {
+            return Err(format!("expected (, found {:?}", tokens.get(*i)));
+        }
+        *i += 1;
+
+        let name = match tokens.get(*i) {
+            Some(Token::Word(name)) => name.clone(),
+            other => {
+                return Err(format!("expected a rule, found {:?}", other));
+            }
+        };
+        *i += 1;
+
+        let result = match &*name {
+            "at-least" => {
+                let n = number(tokens, i)?;
+                Rule::AtLeast(n, quoted(tokens, i)?)
+            }
+            "contains" => {
+                let mut substrings = vec![quoted(tokens, i)?];
+                while let Some(&Token::Quoted(_)) = tokens.get(*i) {
+                    substrings.push(quoted(tokens, i)?);
+                }
+                Rule::Contains(substrings)
+            }
+            "repeat-letter" => Rule::RepeatLetter(number(tokens, i)?),
+            "repeat-pair" => Rule::RepeatPair,
+            "and" => Rule::And(rules(tokens, i)?),
+            "or" => Rule::Or(rules(tokens, i)?),
+            "not" => Rule::Not(Box::new(rule(tokens, i)?)),
+            _ => { return Err(format!("unknown rule: {}", name)); }
+        };
+
+        if tokens.get(*i) != Some(&Token::Close) {
+            return Err(format!(
+                "expected ) to close {}, found {:?}", name, tokens.get(*i)));
+        }
+        *i += 1;
+
+        Ok(result)
+    }
+
+    impl FromStr for Rule {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let tokens = tokenize(s)?;
+            let mut i = 0;
+
+            let result = rule(&tokens, &mut i)?;
+
+            if i < tokens.len() {
+                return Err(format!("unexpected {:?} after rule", tokens[i]));
+            }
+
+            Ok(result)
+        }
+    }
+
+    #[test]
+    fn test_from_str() {
+        let examples: Vec<(&str, Rule)> = vec![
+            (
+                "(repeat-pair)",
+                Rule::RepeatPair,
+            ),
+            (
+                "(at-least 2 \"xyz\") ; trailing comment",
+                Rule::AtLeast(2, String::from("xyz")),
+            ),
+            (
+                "(or (not (contains \"a\" \"b c\")) (repeat-letter 3))",
+                Rule::Or(vec![
+                    Rule::Not(Box::new(Rule::Contains(vec![
+                        String::from("a"),
+                        String::from("b c"),
+                    ]))),
+                    Rule::RepeatLetter(3),
+                ]),
+            ),
+            (
+                "(and)",
+                Rule::And(vec![]),
+            ),
+        ];
+        let bad_examples = [
+            "",
+            "repeat-pair",
+            "(repeat-pair",
+            "(repeat-pair) (repeat-pair)",
+            "(unknown)",
+            "(at-least \"aeiou\" 3)",
+            "(contains)",
+            "(contains \"ab)",
+            "(not)",
+            "(repeat-letter -1)",
+        ];
+
+        for (s, reference) in examples.into_iter() {
+            assert_eq!(s.parse::<Rule>(), Ok(reference));
+        }
+
+        for &s in bad_examples.iter() {
+            assert!(s.parse::<Rule>().is_err(), "parsed {:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let examples: Vec<(&str, &str, bool)> = vec![
+            ( "(at-least 2 \"xyz\")", "axbyc", true ),
+            ( "(at-least 2 \"xyz\")", "axbc", false ),
+            ( "(contains \"ab\" \"cd\")", "xxcdxx", true ),
+            ( "(contains \"ab\" \"cd\")", "acbd", false ),
+            ( "(repeat-letter 2)", "abcad", true ),
+            ( "(repeat-letter 2)", "abacd", false ),
+            ( "(repeat-letter 18446744073709551615)", "abc", false ),
+            ( "(repeat-letter 18446744073709551614)", "abc", false ),
+            ( "(not (repeat-pair))", "abab", false ),
+            ( "(or)", "anything", false ),
+            ( "(and)", "anything", true ),
+        ];
+
+        for (rule, string, reference_result) in examples.into_iter() {
+            let rule: Rule = rule.parse().unwrap();
+
+            assert_eq!(rule.matches(string), reference_result);
+        }
+    }
+
+    #[test]
+    fn test_matches_bytes() {
+        let examples: Vec<(&str, &[u8], bool)> = vec![
+            ( ORIGINAL, b"\xff\xffaei", true ),
+            ( ORIGINAL, b"\xffaei\xfe", false ),
+            ( REVISED, b"\xff\xfe\xff", false ),
+            ( REVISED, b"\xff\xfe\xff\xff\xfe", true ),
+            // a character is as many letters as it has bytes
+            ( "(at-least 2 \"\u{e9}\")", "\u{e9}".as_bytes(), true ),
+            ( "(repeat-letter 1)", "\u{e9}\u{e9}".as_bytes(), true ),
+        ];
+
+        for (rule, bytes, reference_result) in examples.into_iter() {
+            let rule: Rule = rule.parse().unwrap();
+
+            assert_eq!(rule.matches_bytes(bytes), reference_result);
+            assert_eq!(rule.explain_bytes(bytes).passed, reference_result);
+        }
+
+        let rule: Rule = REVISED.parse().unwrap();
+        assert_eq!(rule.explain_bytes(b"a\xffa\xff").to_string(), concat!(
+            "pass (and ...)\n",
+            "  pass (repeat-pair): found a\\xff at 0 and 2\n",
+            "  pass (repeat-letter 1): found a at 0 and 2\n",
+        ));
+        assert_eq!(rule.explain_bytes(b"\xffa\xff").reasons[1].evidence,
+                   Evidence::Repeat(String::from("\\xff"), 0, 2));
+    }
+
+    #[test]
+    fn test_display() {
+        let examples = [
+            ORIGINAL,
+            REVISED,
+            "(or (and) (not (repeat-letter 4)) (at-least 0 \"\"))",
+        ];
+
+        for &s in examples.iter() {
+            let rule: Rule = s.parse().unwrap();
+            let reparsed: Rule = rule.to_string().parse().unwrap();
+
+            assert_eq!(reparsed, rule);
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        let rule: Rule = ORIGINAL.parse().unwrap();
+
+        let explanation = rule.explain_in(Mode::Text, "haegwjzuvuyypxyu");
+        assert!(!explanation.passed);
+        let letters = vec![(1, "a"), (2, "e"), (7, "u"), (9, "u"), (15, "u")];
+        assert_eq!(explanation.reasons[0].evidence, Evidence::Letters(
+            letters.into_iter().map(|(i, l)| (i, l.to_string())).collect()));
+        assert_eq!(explanation.reasons[1].evidence,
+                   Evidence::Repeat(String::from("y"), 10, 11));
+        assert_eq!(explanation.reasons[2].reasons[0].evidence,
+                   Evidence::Substring(13, String::from("xy")));
+
+        assert_eq!(explanation.to_string(), concat!(
+            "fail (and ...)\n",
+            "  pass (at-least 3 \"aeiou\"): ",
+            "found a at 1, e at 2, u at 7, u at 9, u at 15\n",
+            "  pass (repeat-letter 0): found y at 10 and 11\n",
+            "  fail (not ...)\n",
+            "    pass (contains \"ab\" \"cd\" \"pq\" \"xy\"): found xy at 13\n",
+        ));
+
+        let rule: Rule = REVISED.parse().unwrap();
+
+        let explanation = rule.explain_in(Mode::Text, "ieodomkazucvgmuy");
+        assert!(!explanation.passed);
+        assert_eq!(explanation.to_string(), concat!(
+            "fail (and ...)\n",
+            "  fail (repeat-pair): found nothing\n",
+            "  pass (repeat-letter 1): found o at 2 and 4\n",
+        ));
+    }
+
+    #[test]
+    fn test_explain_huge_gap() {
+        let rule: Rule = "(repeat-letter 18446744073709551615)"
+            .parse().unwrap();
+
+        let explanation = rule.explain_in(Mode::Text, "abc");
+        assert!(!explanation.passed);
+        assert_eq!(explanation.evidence, Evidence::Nothing);
+    }
+
+    #[test]
+    fn test_built_in_rules() {
+        use std::fs::File;
+        use std::io::{BufRead, BufReader};
+
+        let original: Rule = ORIGINAL.parse().unwrap();
+        let revised: Rule = REVISED.parse().unwrap();
+
+        let f = File::open("data/day5.txt").unwrap();
+        for line in BufReader::new(f).lines() {
+            let line = line.unwrap();
+
+            assert_eq!(original.matches(&line),
+                       ::string_is_nice(&line));
+            assert_eq!(revised.matches(&line),
+                       ::string_is_nice_revised(&line));
+
+            assert_eq!(original.explain_in(Mode::Text, &line).passed,
+                       original.matches(&line));
+            assert_eq!(revised.explain_in(Mode::Text, &line).passed,
+                       revised.matches(&line));
+        }
+    }
+}
+
+mod corpus {
+    use rules::Rule;
+
+    // xorshift64*, so corpora can be regenerated from a seed
+    // without pulling in a dependency
+    pub struct Rng(u64);
+
+    impl Rng {
+        pub fn new(seed: u64) -> Rng {
+            // the all-zero state would never leave zero
+            Rng(if seed == 0 { 0x9e3779b97f4a7c15 } else { seed })
+        }
+
+        pub fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545f4914f6cdd1d)
+        }
+
+        pub fn below(&mut self, n: usize) -> usize {
+            (self.next_u64() % n as u64) as usize
+        }
+    }
+
+    // strings are drawn at random and kept only if they turn out the
+    // way they were asked for, so give up rather than spin forever
+    const ATTEMPTS: usize = 100000;
+
+    // the top level rules a string can be made to fail one of
+    pub fn parts(rule: &Rule) -> &[Rule] {
+        match *rule {
+            Rule::And(ref rules) => rules,
+            _ => ::std::slice::from_ref(rule),
+        }
+    }
+
+    fn random_string(rng: &mut Rng, length: usize) -> String {
+        let letters = b"abcdefghijklmnopqrstuvwxyz";
+
+        (0..length).map(|_| letters[rng.below(letters.len())] as char)
+            .collect()
+    }
+
+    fn generate<F>(rng: &mut Rng, length: usize, wanted: F) -> Option<String>
+        where F: Fn(&str) -> bool
+    {
+        (0..ATTEMPTS)
+            .map(|_| random_string(rng, length))
+            .find(|s| wanted(s))
+    }
+
+    pub fn nice(rule: &Rule, rng: &mut Rng, length: usize) -> Option<String> {
+        generate(rng, length, |s| rule.matches(s))
+    }
+
+    // passes every top level rule but the one it's meant to fail
+    pub fn failing(rule: &Rule, failed: usize, rng: &mut Rng, length: usize)
+        -> Option<String>
+    {
+        let parts = parts(rule);
+
+        if failed >= parts.len() {
+            return None;
+        }
+
+        generate(rng, length, |s| {
+            parts.iter().enumerate()
+                .all(|(i, part)| part.matches(s) == (i != failed))
+        })
+    }
+
+    #[test]
+    fn test_rng() {
+        let first: Vec<_> = {
+            let mut rng = Rng::new(42);
+            (0..100).map(|_| rng.next_u64()).collect()
+        };
+        let again: Vec<_> = {
+            let mut rng = Rng::new(42);
+            (0..100).map(|_| rng.next_u64()).collect()
+        };
+        let other: Vec<_> = {
+            let mut rng = Rng::new(43);
+            (0..100).map(|_| rng.next_u64()).collect()
+        };
+
+        assert_eq!(first, again);
+        assert!(first != other);
+
+        let mut rng = Rng::new(0);
+        assert!((0..1000).all(|_| rng.below(26) < 26));
+    }
+
+    #[test]
+    fn test_nice() {
+        let rule: Rule = ::rules::ORIGINAL.parse().unwrap();
+        let mut rng = Rng::new(1);
+
+        for _ in 0..200 {
+            let s = nice(&rule, &mut rng, 16).unwrap();
+
+            assert_eq!(s.len(), 16);
+            assert!(::string_is_nice(&s), "{} is not nice", s);
+        }
+
+        let rule: Rule = ::rules::REVISED.parse().unwrap();
+
+        for _ in 0..50 {
+            let s = nice(&rule, &mut rng, 16).unwrap();
+
+            assert!(::string_is_nice_revised(&s), "{} is not nice", s);
+        }
+
+        // too short to ever have three vowels and a double letter
+        let rule: Rule = ::rules::ORIGINAL.parse().unwrap();
+        assert_eq!(nice(&rule, &mut rng, 2), None);
+    }
+
+    #[test]
+    fn test_failing() {
+        let rule: Rule = ::rules::ORIGINAL.parse().unwrap();
+        let mut rng = Rng::new(2);
+
+        let checks: Vec<fn(&str) -> bool> = vec![
+            ::string_contains_three_vowels,
+            ::string_contains_double_letter,
+            |s| !::string_contains_blacklisted_substring(s),
+        ];
+
+        for failed in 0..checks.len() {
+            for _ in 0..50 {
+                let s = failing(&rule, failed, &mut rng, 16).unwrap();
+
+                assert!(!::string_is_nice(&s));
+                for (i, check) in checks.iter().enumerate() {
+                    assert_eq!(check(&s), i != failed, "{} {}", s, i);
+                }
+            }
+        }
+
+        assert_eq!(failing(&rule, 3, &mut rng, 16), None);
+    }
+
+    #[test]
+    fn test_seeded_corpus() {
+        let rule: Rule = ::rules::ORIGINAL.parse().unwrap();
+        let corpus = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..10).map(|_| nice(&rule, &mut rng, 16).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(corpus(7), corpus(7));
+        assert!(corpus(7) != corpus(8));
+    }
+}
+
+mod unicode {
+    #[cfg(test)]
+    use rules::{Mode, Rule};
+
+    // a rough cut of extended grapheme clusters: combining marks and
+    // variation selectors stay with the letter before them, and a zero
+    // width joiner pulls in whatever follows it
+    fn is_extending(c: char) -> bool {
+        matches!(c as u32,
+            0x0300..=0x036f | 0x1ab0..=0x1aff | 0x1dc0..=0x1dff
+            | 0x20d0..=0x20ff | 0xfe20..=0xfe2f
+            | 0xfe00..=0xfe0f
+            | 0x200d)
+    }
+
+    // case folded graphemes, so "E\u{301}" and "e\u{301}" match
+    // each paired with the offset in the string it starts at
+    pub fn graphemes(string: &str) -> Vec<(usize, String)> {
+        let mut graphemes: Vec<(usize, String)> = Vec::new();
+
+        for (i, c) in string.char_indices() {
+            let joins = graphemes.last().is_some_and(|(_, g)| {
+                is_extending(c) || g.ends_with('\u{200d}')
+            });
+
+            if !joins {
+                graphemes.push((i, String::new()));
+            }
+            graphemes.last_mut().unwrap().1.extend(c.to_lowercase());
+        }
+
+        graphemes
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let examples: Vec<(&str, Vec<&str>)> = vec![
+            ( "", vec![] ),
+            ( "aBc", vec!["a", "b", "c"] ),
+            ( "E\u{301}e", vec!["e\u{301}", "e"] ),
+            ( "\u{301}\u{301}a", vec!["\u{301}\u{301}", "a"] ),
+            ( "x\u{200d}yz", vec!["x\u{200d}y", "z"] ),
+            ( "\u{c9}", vec!["\u{e9}"] ),
+        ];
+
+        for (string, reference_graphemes) in examples.into_iter() {
+            let graphemes: Vec<_> = graphemes(string).into_iter()
+                .map(|(_, g)| g).collect();
+            assert_eq!(graphemes, reference_graphemes);
+        }
+
+        let offsets: Vec<_> = graphemes("E\u{301}x\u{200d}yz").into_iter()
+            .map(|(i, _)| i).collect();
+        assert_eq!(offsets, vec![0, 3, 8]);
+    }
+
+    #[test]
+    fn test_string_is_nice() {
+        let examples: Vec<(&str, bool, bool)> = vec![
+            // ascii strings are judged just like before
+            ( "ugknbfddgicrmopn", true, true ),
+            ( "haegwjzuvuyypxyu", false, false ),
+            // case folding
+            ( "UGKNBFDdGICRMOPN", false, true ),
+            ( "ugknbfddgicrmopnAB", true, false ),
+            // accented vowels
+            ( "a\u{301}e\u{301}i\u{301}zz", true, true ),
+            // a letter and the same letter with an accent differ
+            ( "aeioe\u{301}e", false, false ),
+            // doubled accented letters
+            ( "aeie\u{301}e\u{301}", false, true ),
+            ( "aei\u{e9}\u{c9}", false, true ),
+        ];
+
+        let original: Rule = ::rules::ORIGINAL.parse().unwrap();
+
+        for (string, reference_ascii, reference_unicode) in examples {
+            assert_eq!(original.matches(string), reference_ascii);
+            assert_eq!(original.matches_in(Mode::Unicode, string),
+                       reference_unicode);
+            assert_eq!(original.explain_in(Mode::Unicode, string).passed,
+                       reference_unicode);
+        }
+    }
+
+    #[test]
+    fn test_any_rules() {
+        let examples: Vec<(&str, &str, bool)> = vec![
+            // a repeated pair of graphemes, one of them accented
+            ( ::rules::REVISED, "E\u{301}xe\u{301}Xe", true ),
+            ( ::rules::REVISED, "e\u{301}xex", false ),
+            // substrings and classes are folded and cut up too
+            ( "(contains \"E\u{301}X\")", "ae\u{301}x", true ),
+            // there's no normalization, so precomposed letters differ
+            ( "(contains \"\u{c9}X\")", "ae\u{301}x", false ),
+            ( "(contains \"EX\")", "ae\u{301}x", false ),
+            ( "(at-least 2 \"\u{c9}\")", "\u{e9}e\u{301}\u{c9}", true ),
+        ];
+
+        for (rule, string, reference_result) in examples.into_iter() {
+            let rule: Rule = rule.parse().unwrap();
+
+            assert_eq!(rule.matches_in(Mode::Unicode, string),
+                       reference_result, "{} {:?}", rule, string);
+        }
+
+        let rule: Rule = ::rules::REVISED.parse().unwrap();
+        assert_eq!(rule.explain_in(Mode::Unicode, "Ab\u{301}aB\u{301}a")
+            .to_string(), concat!(
+            "pass (and ...)\n",
+            "  pass (repeat-pair): found ab\u{301} at 0 and 4\n",
+            "  pass (repeat-letter 1): found a at 0 and 4\n",
+        ));
+    }
+}
+
+fn read_lines<R: BufRead>(reader: R) -> io::Result<Vec<Vec<u8>>> {
+    // lines are kept as bytes so that invalid utf-8 can't stop us
+    reader.split(b'\n')
+        .map(|line| line.map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            line
+        }))
+        .collect()
+}
+
+#[test]
+fn test_read_lines() {
+    let input: &[u8] = b"one\ntwo\r\n\xff\xfe\n\nlast";
+    let lines = read_lines(input).unwrap();
+
+    assert_eq!(lines, vec![
+        b"one".to_vec(),
+        b"two".to_vec(),
+        b"\xff\xfe".to_vec(),
+        b"".to_vec(),
+        b"last".to_vec(),
+    ]);
+}
+
+struct Options {
+    rules: rules::Rule,
+    mode: Mode,
+    explain: bool,
+    generate: Option<usize>,
+    failed: Option<usize>,
+    length: usize,
+    seed: u64,
+}
+
+fn options<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+    // the original rules unless asked for something else
+    let mut config = String::from(rules::ORIGINAL);
+    let mut mode = Mode::Text;
+    let mut explain = false;
+    let mut generate = None;
+    let mut failed = None;
+    let mut length = 16;
+    let mut seed = 1;
+
+    let number = |arg: &str, n: Option<String>| {
+        n.and_then(|n| n.parse().ok())
+            .ok_or(format!("{} needs a number", arg))
+    };
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--revised" => { config = String::from(rules::REVISED); }
+            "--rules" => {
+                let path = args.next()
+                    .ok_or(String::from("--rules needs a file"))?;
+
+                config.clear();
+                File::open(&path)
+                    .and_then(|mut f| f.read_to_string(&mut config))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            }
+            "--bytes" => { mode = Mode::Bytes; }
+            "--unicode" => { mode = Mode::Unicode; }
+            "--explain" => { explain = true; }
+            "--generate" => {
+                generate = Some(number(&arg, args.next())? as usize);
+            }
+            "--fail" => { failed = Some(number(&arg, args.next())? as usize); }
+            "--length" => { length = number(&arg, args.next())? as usize; }
+            "--seed" => { seed = number(&arg, args.next())?; }
+            _ => { return Err(format!("unknown argument: {}", arg)); }
+        }
+    }
+
+    let rules: rules::Rule = config.parse()?;
+
+    if let Some(failed) = failed {
+        if generate.is_none() {
+            return Err(String::from("--fail only works with --generate"));
+        }
+
+        let parts = corpus::parts(&rules).len();
+        if failed >= parts {
+            return Err(format!(
+                "--fail needs a rule number below {}", parts));
+        }
+    }
+
+    Ok(Options {
+        rules,
+        mode,
+        explain,
+        generate,
+        failed,
+        length,
+        seed,
+    })
+}
+
+#[test]
+fn test_options() {
+    let args = |s: &'static str| s.split_whitespace().map(String::from);
+
+    let parsed = options(args("--generate 5 --fail 2 --length 8")).unwrap();
+    assert_eq!(parsed.generate, Some(5));
+    assert_eq!(parsed.failed, Some(2));
+    assert_eq!(parsed.length, 8);
+
+    let bad_examples = [
+        "--generate",
+        "--length x",
+        "--fail 1",
+        "--generate 5 --fail 3",
+        "--revised --generate 5 --fail 2",
+        "--frobnicate",
+    ];
+
+    for &s in bad_examples.iter() {
+        assert!(options(args(s)).is_err(), "{}", s);
+    }
+}
+
 fn main() {
+    let options = match options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // generated strings are lowercase ascii, which every mode reads alike
+    if let Some(count) = options.generate {
+        let mut rng = corpus::Rng::new(options.seed);
+
+        for _ in 0..count {
+            let string = match options.failed {
+                None => corpus::nice(&options.rules, &mut rng, options.length),
+                Some(failed) => corpus::failing(
+                    &options.rules, failed, &mut rng, options.length),
+            };
+
+            match string {
+                Some(string) => println!("{}", string),
+                None => {
+                    eprintln!("no such string could be found");
+                    process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
     let stdin = io::stdin();
-    let lines: Result<Vec<_>, _> = stdin.lock().lines().collect();
+    let lines = match read_lines(stdin.lock()) {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
-    let count = lines.unwrap().into_iter()
-        .filter(|s| string_is_nice(s)).count();
+    // the original rules have a much faster dedicated scanner
+    let original: rules::Rule = rules::ORIGINAL.parse().unwrap();
+    let fast = options.mode == Mode::Text && options.rules == original;
+
+    let mut count = 0;
+    for (i, line) in lines.iter().enumerate() {
+        // bytes are letters in their own right in byte mode
+        // otherwise invalid utf-8 is reported and counted as naughty
+        let text = match options.mode {
+            Mode::Bytes => None,
+            _ => match str::from_utf8(line) {
+                Ok(text) => Some(text),
+                Err(_) => {
+                    eprintln!("line {}: invalid utf-8, counted as naughty",
+                              i + 1);
+                    continue;
+                }
+            },
+        };
+
+        if options.explain {
+            let explanation = match text {
+                Some(text) => options.rules.explain_in(options.mode, text),
+                None => options.rules.explain_bytes(line),
+            };
+            let verdict = if explanation.passed { "nice" } else { "naughty" };
+
+            println!("{}: {}", String::from_utf8_lossy(line), verdict);
+            print!("{}", explanation);
+            continue;
+        }
 
-    println!("{}", count);
+        let nice = match text {
+            Some(text) if fast && text.is_ascii() => bytes_are_nice(line),
+            Some(text) => options.rules.matches_in(options.mode, text),
+            None => options.rules.matches_bytes(line),
+        };
+
+        count += nice as usize;
+    }
+
+    if !options.explain {
+        println!("{}", count);
+    }
 }
 
 #[test]